@@ -0,0 +1,232 @@
+//! The formatter behind `toml fmt`.
+//!
+//! The style is fixed: no indentation for keys or table headers, one space
+//! on each side of `=`, one blank line before each table header, and at
+//! most one blank line in a row elsewhere.  An array goes on one line if it
+//! fits within the width and holds no comments; otherwise each element goes
+//! on its own line, indented, with a trailing comma.  Comments stay with
+//! the item they're attached to.
+
+use toml_edit::{Array, Decor, DocumentMut, InlineTable, KeyMut, RawString, Table, Value};
+
+pub struct FormatOpts {
+    /// The number of columns an array must fit in to stay on one line.
+    pub width: usize,
+}
+
+/// The indentation for each level of nesting within an array.
+pub const INDENT: &str = "    ";
+
+/// Reformat the document, and return the result.
+pub fn format_document(doc: &mut DocumentMut, opts: &FormatOpts) -> String {
+    doc.decor_mut().clear();
+    format_body(doc.as_table_mut(), &mut true, 0, opts);
+    let trailing = format_prefix(raw_str(Some(doc.trailing())), "", "", false, true);
+    doc.set_trailing(trailing);
+
+    // The first table header gets no blank line before it, and the file
+    // ends with exactly one newline.
+    let out = doc.to_string();
+    let out = out.trim_start_matches('\n').trim_end();
+    if out.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n", out)
+    }
+}
+
+/// Format the keys and values of a table, and any tables within it.
+///
+/// For a dotted table like `a` in `a.b = 1`, which shares its lines with
+/// its parent, `first` and `path_len` carry on from the parent.
+fn format_body(table: &mut Table, first: &mut bool, path_len: usize, opts: &FormatOpts) {
+    use toml_edit::Item;
+    for (mut key, item) in table.iter_mut() {
+        match item {
+            Item::None => {}
+            Item::Value(value) => {
+                format_key_value(&mut key, value, !*first, path_len, opts);
+                *first = false;
+            }
+            Item::Table(t) if t.is_dotted() => {
+                clear_key_decor(&mut key);
+                let path_len = path_len + key.display_repr().len() + 1;
+                format_body(t, first, path_len, opts);
+            }
+            Item::Table(t) => {
+                clear_key_decor(&mut key);
+                format_header(t.decor_mut());
+                format_body(t, &mut true, 0, opts);
+            }
+            Item::ArrayOfTables(a) => {
+                clear_key_decor(&mut key);
+                for t in a.iter_mut() {
+                    format_header(t.decor_mut());
+                    format_body(t, &mut true, 0, opts);
+                }
+            }
+        }
+    }
+}
+
+fn format_key_value(
+    key: &mut KeyMut,
+    value: &mut Value,
+    blank_ok: bool,
+    path_len: usize,
+    opts: &FormatOpts,
+) {
+    let prefix = format_prefix(raw_str(key.leaf_decor().prefix()), "", "", false, blank_ok);
+    *key.leaf_decor_mut() = Decor::new(prefix, " ");
+    key.dotted_decor_mut().clear();
+    let suffix = format_suffix(raw_str(value.decor().suffix()));
+    *value.decor_mut() = Decor::new(" ", suffix);
+    let column = path_len + key.display_repr().len() + " = ".len();
+    format_value(value, "", column, opts);
+}
+
+/// Format a value, which starts at the given column in a line with the
+/// given indentation.
+fn format_value(value: &mut Value, indent: &str, column: usize, opts: &FormatOpts) {
+    match value {
+        Value::Array(array) => format_array(array, indent, column, opts),
+        Value::InlineTable(table) => format_inline_table(table),
+        _ => {}
+    }
+}
+
+fn format_array(array: &mut Array, indent: &str, column: usize, opts: &FormatOpts) {
+    let inner = format!("{}{}", indent, INDENT);
+    for value in array.iter_mut() {
+        format_value(value, &inner, inner.len(), opts);
+    }
+
+    let has_comments = raw_str(Some(array.trailing())).contains('#')
+        || array.iter().any(|value| {
+            let decor = value.decor();
+            raw_str(decor.prefix()).contains('#') || raw_str(decor.suffix()).contains('#')
+        });
+    if !has_comments {
+        let mut one_line = array.clone();
+        one_line.fmt();
+        let text = one_line.to_string();
+        let text = text.trim();
+        if !text.contains('\n') && column + text.len() <= opts.width {
+            *array = one_line;
+            return;
+        }
+    }
+
+    for value in array.iter_mut() {
+        let decor = value.decor();
+        let prefix = format_prefix(raw_str(decor.prefix()), &inner, &inner, true, true);
+        // A comment here would swallow the comma, so it needs its own line.
+        let suffix = match format_suffix(raw_str(decor.suffix())) {
+            s if s.is_empty() => s,
+            s => format!("{}\n{}", s, inner),
+        };
+        *value.decor_mut() = Decor::new(prefix, suffix);
+    }
+    let trailing = format_prefix(raw_str(Some(array.trailing())), &inner, indent, true, true);
+    array.set_trailing(trailing);
+    array.set_trailing_comma(!array.is_empty());
+}
+
+fn format_inline_table(table: &mut InlineTable) {
+    table.set_preamble("");
+    for (mut key, value) in table.iter_mut() {
+        clear_key_decor(&mut key);
+        value.decor_mut().clear();
+        // An inline table belongs on one line, however long.
+        format_value(value, "", 0, &FormatOpts { width: usize::MAX });
+    }
+}
+
+fn format_header(decor: &mut Decor) {
+    let prefix = format_prefix(raw_str(decor.prefix()), "", "", false, false);
+    let suffix = format_suffix(raw_str(decor.suffix()));
+    *decor = Decor::new(format!("\n{}", prefix), suffix);
+}
+
+fn clear_key_decor(key: &mut KeyMut) {
+    key.leaf_decor_mut().clear();
+    key.dotted_decor_mut().clear();
+}
+
+pub fn raw_str(raw: Option<&RawString>) -> &str {
+    raw.and_then(RawString::as_str).unwrap_or("")
+}
+
+/// Format the whitespace and comments before an item.
+///
+/// Each comment goes on its own line at `comment_indent`, with at most one
+/// blank line in a row, and none at the start unless `blank_ok`.  The item
+/// itself then starts at `indent`.  With `same_line`, as for an element of
+/// an array, the text runs on from the end of the previous item's line,
+/// where a comment may stay.
+fn format_prefix(
+    prefix: &str,
+    comment_indent: &str,
+    indent: &str,
+    same_line: bool,
+    blank_ok: bool,
+) -> String {
+    let mut lines: Vec<&str> = prefix.split('\n').collect();
+    // The last line is just the whitespace before the item.
+    lines.pop();
+    let mut lines = lines.into_iter();
+
+    let mut out = String::new();
+    if same_line {
+        if let Some(first) = lines.next().map(str::trim).filter(|l| !l.is_empty()) {
+            out.push(' ');
+            out.push_str(first);
+        }
+        out.push('\n');
+    }
+    let mut blank_ok = blank_ok;
+    let mut blank = false;
+    for line in lines.map(str::trim) {
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && blank_ok {
+            out.push('\n');
+        }
+        out.push_str(comment_indent);
+        out.push_str(line);
+        out.push('\n');
+        blank = false;
+        blank_ok = true;
+    }
+    if blank && blank_ok {
+        out.push('\n');
+    }
+    out.push_str(indent);
+    out
+}
+
+/// Format the whitespace and any comment after an item, on the same line.
+fn format_suffix(suffix: &str) -> String {
+    match suffix.trim() {
+        comment if comment.starts_with('#') => format!(" {}", comment),
+        _ => String::new(),
+    }
+}
+
+#[test]
+fn test_format_prefix() {
+    for (prefix, same_line, blank_ok, expected) in [
+        ("", false, false, ""),
+        ("  ", false, true, ""),
+        ("\n\n  # a  \n\n\n# b\n\n  ", false, false, "# a\n\n# b\n\n"),
+        ("\n\n# a\n", false, true, "\n# a\n"),
+        (" ", true, true, "\n    "),
+        (" # a\n  # b\n  ", true, true, " # a\n    # b\n    "),
+    ] {
+        let indent = if same_line { "    " } else { "" };
+        let actual = format_prefix(prefix, indent, indent, same_line, blank_ok);
+        assert_eq!(expected, actual, "{:?}", prefix);
+    }
+}
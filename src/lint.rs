@@ -0,0 +1,514 @@
+//! Warnings about TOML that's valid but likely a mistake, for `toml check`.
+//!
+//! Some rules look at the data, like keys that differ only by case, and
+//! some at the text, like trailing whitespace.  Text inside a multi-line
+//! string is part of the data, so the text rules skip it.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::str;
+
+use toml_edit::{ImDocument, Item, Table, TableLike, Value};
+
+use crate::query_parser::{format_tpath, TpathSegment};
+
+/// A kind of thing to warn about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    KeyCase,
+    LookalikeKey,
+    MixedArray,
+    TableOrder,
+    LongInlineTable,
+    FloatPrecision,
+    TrailingWhitespace,
+    Indentation,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 8] = [
+        Rule::KeyCase,
+        Rule::LookalikeKey,
+        Rule::MixedArray,
+        Rule::TableOrder,
+        Rule::LongInlineTable,
+        Rule::FloatPrecision,
+        Rule::TrailingWhitespace,
+        Rule::Indentation,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Rule::KeyCase => "key-case",
+            Rule::LookalikeKey => "lookalike-key",
+            Rule::MixedArray => "mixed-array",
+            Rule::TableOrder => "table-order",
+            Rule::LongInlineTable => "long-inline-table",
+            Rule::FloatPrecision => "float-precision",
+            Rule::TrailingWhitespace => "trailing-whitespace",
+            Rule::Indentation => "indentation",
+        }
+    }
+}
+
+/// The names of all the rules, in the order of `Rule::ALL`.
+pub const RULE_NAMES: [&str; Rule::ALL.len()] = {
+    let mut names = [""; Rule::ALL.len()];
+    let mut i = 0;
+    while i < names.len() {
+        names[i] = Rule::ALL[i].name();
+        i += 1;
+    }
+    names
+};
+
+impl str::FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Rule::ALL
+            .into_iter()
+            .find(|rule| rule.name() == s)
+            .ok_or_else(|| format!("unknown rule: {}", s))
+    }
+}
+
+pub struct Warning {
+    pub rule: Rule,
+    /// Where in the file the problem is, as a byte offset.
+    pub offset: usize,
+    pub message: String,
+}
+
+/// Check the document by the given rules, and return the warnings in the
+/// order they appear in the file.
+///
+/// An inline table is too long if it takes more than `max_inline_width`
+/// characters.
+pub fn check(doc: &ImDocument<String>, rules: &[Rule], max_inline_width: usize) -> Vec<Warning> {
+    let mut lint = Lint {
+        rules,
+        raw: doc.raw(),
+        max_inline_width,
+        in_long_inline: false,
+        multiline_strings: vec![],
+        warnings: vec![],
+    };
+    lint.item(doc.as_item(), &mut vec![]);
+    lint.table_order(doc.as_table());
+    lint.text();
+    lint.warnings.sort_by_key(|w| w.offset);
+    lint.warnings
+}
+
+struct Lint<'a> {
+    rules: &'a [Rule],
+    raw: &'a str,
+    max_inline_width: usize,
+    /// Whether we're within an inline table already warned about as long.
+    in_long_inline: bool,
+    /// The spans of the multi-line strings in the document.
+    multiline_strings: Vec<Range<usize>>,
+    warnings: Vec<Warning>,
+}
+
+impl Lint<'_> {
+    fn warn(&mut self, rule: Rule, offset: usize, message: String) {
+        if self.rules.contains(&rule) {
+            self.warnings.push(Warning {
+                rule,
+                offset,
+                message,
+            });
+        }
+    }
+
+    fn item(&mut self, item: &Item, path: &mut Vec<TpathSegment>) {
+        match item {
+            Item::Table(table) => self.table(table, path),
+            Item::ArrayOfTables(array) => {
+                for (i, table) in array.iter().enumerate() {
+                    path.push(TpathSegment::Num(i));
+                    self.table(table, path);
+                    path.pop();
+                }
+            }
+            Item::Value(value) => self.value(value, path),
+            Item::None => {}
+        }
+    }
+
+    fn table(&mut self, table: &dyn TableLike, path: &mut Vec<TpathSegment>) {
+        let mut lowercase: HashMap<String, &str> = HashMap::new();
+        for (name, item) in table.iter() {
+            let offset = table
+                .key(name)
+                .and_then(|key| key.span())
+                .or_else(|| item.span())
+                .map_or(0, |span| span.start);
+            path.push(TpathSegment::Name(name.to_owned()));
+            match lowercase.get(&name.to_lowercase()) {
+                Some(other) => {
+                    let message = format!(
+                        "key `{}` differs only by case from `{}`",
+                        format_tpath(path),
+                        name_tpath(path, other),
+                    );
+                    self.warn(Rule::KeyCase, offset, message);
+                }
+                None => {
+                    lowercase.insert(name.to_lowercase(), name);
+                }
+            }
+            if let Some(lookalike) = bare_lookalike(name) {
+                let odd: Vec<_> = name
+                    .chars()
+                    .filter(|c| !c.is_ascii())
+                    .map(|c| format!("U+{:04X}", c as u32))
+                    .collect();
+                let message = format!(
+                    "key `{}` looks like `{}`, but has {}",
+                    format_tpath(path),
+                    name_tpath(path, &lookalike),
+                    odd.join(", "),
+                );
+                self.warn(Rule::LookalikeKey, offset, message);
+            }
+            self.item(item, path);
+            path.pop();
+        }
+    }
+
+    fn value(&mut self, value: &Value, path: &mut Vec<TpathSegment>) {
+        let span = value.span().unwrap_or_default();
+        let (offset, raw) = (span.start, &self.raw[span.clone()]);
+        match value {
+            Value::String(_) => {
+                if raw.starts_with("\"\"\"") || raw.starts_with("'''") {
+                    self.multiline_strings.push(span);
+                }
+            }
+            Value::Float(f) => {
+                if let Some(read) = lost_precision(raw, *f.value()) {
+                    let message = format!(
+                        "float `{}` can't be stored exactly as written, and is read as {}",
+                        format_tpath(path),
+                        read,
+                    );
+                    self.warn(Rule::FloatPrecision, offset, message);
+                }
+            }
+            Value::Array(array) => {
+                let mut types = vec![];
+                for value in array.iter() {
+                    if !types.contains(&value.type_name()) {
+                        types.push(value.type_name());
+                    }
+                }
+                if types.len() > 1 {
+                    let (last, rest) = types.split_last().unwrap_or((&"", &[]));
+                    let message = format!(
+                        "array `{}` mixes {} and {}",
+                        format_tpath(path),
+                        rest.join(", "),
+                        last,
+                    );
+                    self.warn(Rule::MixedArray, offset, message);
+                }
+                for (i, value) in array.iter().enumerate() {
+                    path.push(TpathSegment::Num(i));
+                    self.value(value, path);
+                    path.pop();
+                }
+            }
+            Value::InlineTable(table) => {
+                let width = table
+                    .span()
+                    .map_or(0, |span| self.raw[span].chars().count());
+                let outer = self.in_long_inline;
+                if !outer && width > self.max_inline_width {
+                    let message = format!(
+                        "inline table `{}` is {} characters long, more than {}",
+                        format_tpath(path),
+                        width,
+                        self.max_inline_width,
+                    );
+                    self.warn(Rule::LongInlineTable, offset, message);
+                    self.in_long_inline = true;
+                }
+                self.table(table, path);
+                self.in_long_inline = outer;
+            }
+            Value::Integer(_) | Value::Boolean(_) | Value::Datetime(_) => {}
+        }
+    }
+
+    /// Warn about each table header that comes apart from the others in its
+    /// group, like `[a.c]` after `[b]` in a file with `[a]` before that.
+    fn table_order(&mut self, root: &Table) {
+        let mut headers = vec![];
+        header_paths(root, &mut vec![], &mut headers);
+        headers.sort_by_key(|(position, _, _)| *position);
+
+        for i in 1..headers.len() {
+            let (_, path, offset) = &headers[i];
+            let (_, prev, _) = &headers[i - 1];
+            let group = (1..=path.len()).map(|n| &path[..n]).find(|group| {
+                !prev.starts_with(group)
+                    && headers[..i - 1]
+                        .iter()
+                        .any(|(_, earlier, _)| earlier.starts_with(group))
+            });
+            if let Some(group) = group {
+                let others = match group.len() == path.len() {
+                    true => format!("the other `{}` tables", format_names(group)),
+                    false => format!("the rest of `{}`", format_names(group)),
+                };
+                let message = format!(
+                    "table `{}` is apart from {}, after `{}`",
+                    format_names(path),
+                    others,
+                    format_names(prev),
+                );
+                self.warn(Rule::TableOrder, *offset, message);
+            }
+        }
+    }
+
+    fn text(&mut self) {
+        let raw = self.raw;
+        let in_string = |offset: usize| {
+            self.multiline_strings
+                .iter()
+                .any(|span| span.start < offset && offset < span.end)
+        };
+        let mut warnings = vec![];
+        // The indentation of the first indented line in this block, and its
+        // line number.  A block is a run of indented lines, like the elements
+        // of an array or the keys of a table, and ends at an unindented one.
+        let mut indent_style: Option<(char, usize)> = None;
+        let mut start = 0;
+        for (number, line) in raw.split('\n').enumerate() {
+            let text = line.strip_suffix('\r').unwrap_or(line);
+            let code = text.trim_end_matches([' ', '\t']);
+            if code.len() < text.len() && !in_string(start + code.len()) {
+                warnings.push((
+                    Rule::TrailingWhitespace,
+                    start + code.len(),
+                    "trailing whitespace".to_owned(),
+                ));
+            }
+
+            let indent = &code[..code.len() - code.trim_start_matches([' ', '\t']).len()];
+            if indent.is_empty() && !code.is_empty() && !in_string(start) {
+                indent_style = None;
+            } else if !indent.is_empty() && !in_string(start) {
+                let first = indent.chars().next().unwrap_or(' ');
+                match indent_style {
+                    None if indent.contains(['\t']) && indent.contains([' ']) => {
+                        warnings.push((
+                            Rule::Indentation,
+                            start,
+                            "indentation mixes tabs and spaces".to_owned(),
+                        ));
+                    }
+                    None => indent_style = Some((first, number + 1)),
+                    Some((style, first_line)) => {
+                        if let Some(other) = indent.chars().find(|&c| c != style) {
+                            let message = format!(
+                                "indented with {}, but line {} is indented with {}",
+                                whitespace_name(other),
+                                first_line,
+                                whitespace_name(style),
+                            );
+                            warnings.push((Rule::Indentation, start, message));
+                        }
+                    }
+                }
+            }
+            start += line.len() + 1;
+        }
+        for (rule, offset, message) in warnings {
+            self.warn(rule, offset, message);
+        }
+    }
+}
+
+/// The tpath of a sibling key, with the given name in place of the last.
+fn name_tpath(path: &[TpathSegment], name: &str) -> String {
+    let mut path = path.to_vec();
+    path.pop();
+    path.push(TpathSegment::Name(name.to_owned()));
+    format_tpath(&path)
+}
+
+fn format_names(names: &[String]) -> String {
+    let path: Vec<_> = names.iter().cloned().map(TpathSegment::Name).collect();
+    format_tpath(&path)
+}
+
+/// The position, key names, and byte offset of each table header within
+/// the table.
+fn header_paths(table: &Table, path: &mut Vec<String>, out: &mut Vec<(usize, Vec<String>, usize)>) {
+    for (name, item) in table.iter() {
+        path.push(name.to_owned());
+        let tables: Vec<&Table> = match item {
+            Item::Table(table) => vec![table],
+            Item::ArrayOfTables(array) => array.iter().collect(),
+            _ => vec![],
+        };
+        for table in tables {
+            if let Some(position) = table.position() {
+                if !table.is_implicit() && !table.is_dotted() {
+                    let offset = table.span().map_or(0, |span| span.start);
+                    out.push((position, path.clone(), offset));
+                }
+            }
+            header_paths(table, path, out);
+        }
+        path.pop();
+    }
+}
+
+fn whitespace_name(c: char) -> &'static str {
+    match c {
+        '\t' => "tabs",
+        _ => "spaces",
+    }
+}
+
+/// If the key isn't ASCII but looks like a bare key, the bare key it
+/// looks like.
+fn bare_lookalike(name: &str) -> Option<String> {
+    if name.is_ascii() {
+        return None;
+    }
+    let mut bare = String::new();
+    for c in name.chars() {
+        match ascii_lookalike(c) {
+            Some('\0') => {}
+            Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-' => bare.push(c),
+            _ => return None,
+        }
+    }
+    Some(bare).filter(|bare| !bare.is_empty())
+}
+
+/// The ASCII character this one looks like, if any, or `'\0'` if it's
+/// invisible.
+fn ascii_lookalike(c: char) -> Option<char> {
+    let ascii = match c {
+        _ if c.is_ascii() => c,
+        // Zero-width spaces and joiners, and the byte order mark.
+        '\u{200b}'..='\u{200d}' | '\u{2060}' | '\u{feff}' => '\0',
+        // Fullwidth forms of ASCII.
+        '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0)?,
+        // Hyphens and dashes.
+        '\u{2010}'..='\u{2013}' | '\u{2212}' => '-',
+        // Cyrillic.
+        'а' => 'a',
+        'е' => 'e',
+        'о' => 'o',
+        'р' => 'p',
+        'с' => 'c',
+        'у' => 'y',
+        'х' => 'x',
+        'і' => 'i',
+        'ј' => 'j',
+        'ѕ' => 's',
+        'ԁ' => 'd',
+        'А' => 'A',
+        'В' => 'B',
+        'Е' => 'E',
+        'К' => 'K',
+        'М' => 'M',
+        'Н' => 'H',
+        'О' => 'O',
+        'Р' => 'P',
+        'С' => 'C',
+        'Т' => 'T',
+        'Х' => 'X',
+        // Greek.
+        'ο' => 'o',
+        'α' => 'a',
+        'ν' => 'v',
+        'Α' => 'A',
+        'Β' => 'B',
+        'Ε' => 'E',
+        'Ζ' => 'Z',
+        'Η' => 'H',
+        'Ι' => 'I',
+        'Κ' => 'K',
+        'Μ' => 'M',
+        'Ν' => 'N',
+        'Ο' => 'O',
+        'Ρ' => 'P',
+        'Τ' => 'T',
+        'Υ' => 'Y',
+        'Χ' => 'X',
+        _ => return None,
+    };
+    Some(ascii)
+}
+
+/// If the float as written isn't the number it's read as, that number.
+fn lost_precision(raw: &str, value: f64) -> Option<String> {
+    // `inf` and `nan` are what they say; and a float too large to store is
+    // a parse error.
+    if !value.is_finite() {
+        return None;
+    }
+    let read = format!("{:e}", value);
+    (decimal(raw)? != decimal(&read)?).then(|| format!("{:?}", value))
+}
+
+/// The significant digits and exponent of a decimal number, like `("15",
+/// -1)` for `1.50`, ignoring the sign.
+fn decimal(s: &str) -> Option<(String, i64)> {
+    let s = s.replace('_', "");
+    let s = s.trim_start_matches(['+', '-']);
+    let (mantissa, exp) = match s.split_once(['e', 'E']) {
+        Some((mantissa, exp)) => (mantissa, exp.parse::<i64>().ok()?),
+        None => (s, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", int, frac);
+    let trimmed = digits.trim_end_matches('0');
+    let exp = exp - frac.len() as i64 + (digits.len() - trimmed.len()) as i64;
+    match trimmed.trim_start_matches('0') {
+        "" => Some((String::new(), 0)),
+        digits => Some((digits.to_owned(), exp)),
+    }
+}
+
+#[test]
+fn test_decimal() {
+    assert_eq!(decimal("1.50"), Some(("15".into(), -1)));
+    assert_eq!(decimal("-0.001_5e3"), Some(("15".into(), -1)));
+    assert_eq!(decimal("1.5e-1"), Some(("15".into(), -2)));
+    assert_eq!(decimal("0.0"), Some(("".into(), 0)));
+    assert_eq!(decimal("1200"), Some(("12".into(), 2)));
+}
+
+#[test]
+fn test_lost_precision() {
+    assert_eq!(lost_precision("0.1", 0.1), None);
+    assert_eq!(lost_precision("1e300", 1e300), None);
+    assert_eq!(lost_precision("-inf", f64::NEG_INFINITY), None);
+    assert_eq!(
+        lost_precision("0.1000000000000000000001", 0.1),
+        Some("0.1".into())
+    );
+    assert_eq!(
+        lost_precision("9007199254740993.0", 9007199254740992.0),
+        Some("9007199254740992.0".into())
+    );
+}
+
+#[test]
+fn test_bare_lookalike() {
+    assert_eq!(bare_lookalike("name"), None);
+    assert_eq!(bare_lookalike("n\u{430}me"), Some("name".into()));
+    assert_eq!(bare_lookalike("\u{ff4e}ame\u{200b}"), Some("name".into()));
+    assert_eq!(bare_lookalike("naïve"), None);
+}
@@ -1,21 +1,62 @@
+mod format;
+mod lint;
 mod query_parser;
+mod schema;
+mod sort;
 
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{self, exit};
 use std::str;
-use std::{fs, process::exit};
 
 use anyhow::Error;
 use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
 use structopt::StructOpt;
 use thiserror::Error;
-use toml_edit::{value, Document, Item, Table, Value};
+use toml_edit::{value, Array, Decor, DocumentMut, ImDocument, Item, Table, Value};
 
-use query_parser::{parse_query, Query, TpathSegment};
+use format::{format_document, raw_str, FormatOpts, INDENT};
+use lint::Rule;
+use query_parser::{format_tpath, parse_query, Query, QueryError, TpathSegment};
+use schema::Schema;
+
+/// The exit status for each kind of failure.  See `ErrorKind::exit_code`.
+const EXIT_STATUS_HELP: &str = "\
+EXIT STATUS:
+    0    Success
+    1    The key was not found (or array index was out of bounds), or
+         with `get --exit-status`, the result was false or empty, or
+         with `fmt --check` or `sort --check`, a file was not formatted
+         or sorted, or with `validate`, a file didn't match the schema,
+         or with `check`, there were warnings
+    2    Usage error, including a syntax error in a query
+    3    I/O error, as when a file doesn't exist
+    4    TOML parse error
+    5    Type conflict, as when indexing into a non-array with a number
+    6    Any other error";
 
 // TODO: Get more of the description in the README into the CLI help.
 #[derive(StructOpt)]
-#[structopt(about)]
-enum Args {
+#[structopt(about, after_help = EXIT_STATUS_HELP)]
+struct Args {
+    /// How to print errors: `human`, or `json` for one JSON object per error
+    #[structopt(
+        long,
+        global = true,
+        default_value = "human",
+        possible_values = &["human", "json"],
+    )]
+    error_format: ErrorFormat,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
     /// Print some data from the file
     ///
     /// Read the given TOML file, find the data within it at the given query,
@@ -27,8 +68,375 @@ enum Args {
     /// Output is JSON by default.  With `--raw`/`-r`, if the data is a
     /// string, print it directly.  With `--output-toml`, print the data
     /// as a fragment of TOML.
+    ///
+    /// Several queries may be given; the file is read just once.  The
+    /// results are then printed as a JSON array, or with `--raw`/`-r`,
+    /// one per line.
+    ///
+    /// To query several files, pass `--file`/`-f` once per file (or with
+    /// a glob pattern) in place of the path argument.  Each line of output
+    /// is then prefixed with the file's name, like `grep -H`.
+    ///
+    /// With `--layered`, the files are instead layers of configuration,
+    /// from lowest to highest precedence: for each query, print the value
+    /// from the last file that has it.  A table is taken whole from that
+    /// one file; it isn't merged with tables of the same name in the others.
+    /// Files that don't exist are skipped.
     // Without verbatim_doc_comment, the paragraphs get rewrapped to like
     // 120 columns wide.
+    #[structopt(
+        verbatim_doc_comment,
+        usage = "toml get [FLAGS] [OPTIONS] <path> <query>...\n    \
+                 toml get [FLAGS] [OPTIONS] --file <file>... <query>..."
+    )]
+    Get {
+        /// Path to the TOML file to read (unless `--file` is given), then
+        /// one or more queries within the TOML data (e.g. `dependencies.serde`,
+        /// `foo[0].bar`)
+        #[structopt(parse(from_os_str), required = true, value_name = "args")]
+        args: Vec<OsString>,
+
+        #[structopt(flatten)]
+        files: FileOpts,
+
+        #[structopt(flatten)]
+        opts: GetOpts,
+    },
+
+    /// Check whether the file has some data
+    ///
+    /// Exit with success if the TOML document has the given key, and with
+    /// failure if not.  Print nothing either way.
+    #[structopt(verbatim_doc_comment)]
+    Exists {
+        /// Path to the TOML file to read
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Query within the TOML data (e.g. `dependencies.serde`, `foo[0].bar`)
+        query: String,
+    },
+
+    /// Print all the data in the file, one `query = value` line per value
+    ///
+    /// Each line gives a query for a value, in the syntax `get` accepts,
+    /// and the value as written in the TOML file.  Arrays and tables are
+    /// broken down into their elements, so that each line holds a single
+    /// value; empty arrays and tables are printed as `[]` and `{}`.
+    ///
+    /// With a query, print just the data within that part of the file.
+    #[structopt(verbatim_doc_comment)]
+    List {
+        /// Path to the TOML file to read
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Query within the TOML data (e.g. `dependencies`, `foo[0].bar`)
+        query: Option<String>,
+    },
+
+    /// List the keys of a table, one per line, in document order
+    Keys {
+        /// Path to the TOML file to read
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Query within the TOML data (e.g. `dependencies`, `foo[0].bar`)
+        query: String,
+    },
+
+    /// Print the TOML type of some data
+    ///
+    /// The type is one of: table, inline-table, array-of-tables, array,
+    /// string, integer, float, boolean, offset-datetime, local-datetime,
+    /// local-date, local-time.
+    #[structopt(verbatim_doc_comment)]
+    Type {
+        /// Path to the TOML file to read
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Query within the TOML data (e.g. `dependencies.serde`, `foo[0].bar`)
+        query: String,
+    },
+
+    /// Print the number of elements in an array, or entries in a table
+    Length {
+        /// Path to the TOML file to read
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Query within the TOML data (e.g. `dependencies`, `foo[0].bar`)
+        query: String,
+    },
+
+    /// Read or change the comment on a key, table header, or array element
+    Comment {
+        #[structopt(subcommand)]
+        command: CommentCommand,
+    },
+
+    /// Reformat TOML files in a standard style
+    ///
+    /// Each file is rewritten in place.  Keys and table headers are not
+    /// indented, `=` gets one space on each side, and each table header
+    /// one blank line before it.  An array that doesn't fit within the
+    /// width, or that holds comments, gets one element per line with a
+    /// trailing comma.  Comments stay with the items they're on.
+    ///
+    /// With `--check`, leave the files alone; instead print a diff for each
+    /// file that isn't formatted, and exit with failure if there are any.
+    #[structopt(verbatim_doc_comment)]
+    Fmt {
+        /// Paths to the TOML files to format
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Print a diff for each file that isn't formatted, rather than
+        /// formatting it
+        #[structopt(long)]
+        check: bool,
+
+        /// Put an array on one line only if it fits in this many columns
+        #[structopt(long, default_value = "80", value_name = "columns")]
+        width: usize,
+    },
+
+    /// Sort the keys of a table, or the elements of an array
+    ///
+    /// The file is rewritten in place.  For a table (by default, the whole
+    /// document), its keys are sorted, and so are the tables within it;
+    /// with `--recursive`, the same goes for every table within it.  An
+    /// array of strings, numbers, etc. is sorted by value.  An array of
+    /// tables is sorted by the value at the key given with `--by`, with
+    /// tables lacking that key at the end.  Comments move with the keys
+    /// and elements they're on.
+    ///
+    /// With `--check`, leave the file alone; instead print a diff if it
+    /// isn't sorted, and exit with failure.
+    #[structopt(verbatim_doc_comment)]
+    Sort {
+        /// Path to the TOML file to sort
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Query for the table or array to sort within the TOML data (e.g.
+        /// `dependencies`, `bin`), or `.` for the whole document
+        query: Option<String>,
+
+        /// Also sort every table within the table
+        #[structopt(long, short)]
+        recursive: bool,
+
+        /// Sort an array of tables by the value at this key (or query, like
+        /// `package.name`) in each
+        #[structopt(long, value_name = "key")]
+        by: Option<String>,
+
+        /// Print a diff if the file isn't sorted, rather than sorting it
+        #[structopt(long)]
+        check: bool,
+    },
+
+    /// Move some data to another place in the file, and print the result
+    ///
+    /// The key/value pair, table, or array of tables at `from` is moved to
+    /// `to`, along with its comments, and the modified document printed.
+    /// Tables on the way to `to` are created as needed, as with `set`.  A
+    /// table moved into an inline table or array becomes an inline table.
+    ///
+    /// If there's already data at `to`, fail, unless `--force` is given.
+    #[structopt(verbatim_doc_comment)]
+    Mv {
+        /// Path to the TOML file to read
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Query for the data to move (e.g. `server.addr`)
+        from: String,
+
+        /// Query for where to move it (e.g. `server.listen.address`)
+        to: String,
+
+        /// Replace any data already at the destination
+        #[structopt(long)]
+        force: bool,
+    },
+
+    /// Copy some data into a file, and print the result
+    ///
+    /// The key/value pair, table, or array of tables at `src-query` in
+    /// `src-path` is copied, with its formatting and comments, to
+    /// `dst-query` in `dst-path`, and the modified document printed.
+    /// Tables on the way are created as needed, as with `set`.
+    ///
+    /// The copy takes the form its place calls for: within an inline table
+    /// or array, or in place of an inline table, a table becomes an inline
+    /// table, and in place of a table, an inline table becomes a table.
+    ///
+    /// If there's already data at `dst-query`, fail, unless `--force` is given.
+    #[structopt(verbatim_doc_comment)]
+    Cp {
+        /// Path to the TOML file to copy from
+        #[structopt(parse(from_os_str))]
+        src_path: PathBuf,
+
+        /// Query for the data to copy (e.g. `profile.release`)
+        src_query: String,
+
+        /// Path to the TOML file to copy to
+        #[structopt(parse(from_os_str))]
+        dst_path: PathBuf,
+
+        /// Query for where to copy it (e.g. `profile.bench`)
+        dst_query: String,
+
+        /// Replace any data already at the destination
+        #[structopt(long)]
+        force: bool,
+    },
+
+    /// Convert a table to or from an inline table, and print the result
+    ///
+    /// The table at the query is written in the given style: as a `[a.b]`
+    /// section, as an inline table like `b = { c = 1 }`, or with dotted
+    /// keys like `b.c = 1`.  An array of tables converts to an array of
+    /// inline tables, and back, in the same way.  The keys stay in order,
+    /// and so do comments where there's a place for them: an inline table
+    /// can hold none, but the line before it can.
+    #[structopt(verbatim_doc_comment)]
+    Convert {
+        /// Path to the TOML file to read
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Query for the table or array of tables within the TOML data
+        /// (e.g. `dependencies.serde`)
+        query: String,
+
+        /// The style to convert to: `table`, `inline`, or `dotted`
+        #[structopt(
+            long,
+            value_name = "style",
+            possible_values = &["table", "inline", "dotted"],
+        )]
+        to: TableStyle,
+    },
+
+    /// Check TOML files against a JSON Schema
+    ///
+    /// Each file's data is checked in its JSON form, as `get` prints it.
+    /// Each spot that doesn't match the schema is printed on a line of its
+    /// own, like `FILE:LINE:COL: QUERY: MESSAGE`, and the command exits
+    /// with failure if there are any.  For a spot with no place in the file,
+    /// like a key missing from the top level, the line and column are left
+    /// out.
+    #[structopt(verbatim_doc_comment)]
+    Validate {
+        /// Path to the JSON Schema to check against
+        #[structopt(long, parse(from_os_str), value_name = "path")]
+        schema: PathBuf,
+
+        /// Paths to the TOML files to check
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Warn about TOML that's valid but likely a mistake
+    ///
+    /// Each warning is printed on a line of its own, like
+    /// `FILE:LINE:COL: RULE: MESSAGE`, and the command exits with failure
+    /// if there are any.  The rules are:
+    ///
+    ///   key-case             keys in a table that differ only by case
+    ///   lookalike-key        quoted keys with non-ASCII characters that
+    ///                        look like a bare key
+    ///   mixed-array          arrays with elements of more than one type
+    ///   table-order          tables apart from the others they go with
+    ///   long-inline-table    inline tables longer than `--max-inline-width`
+    ///   float-precision      floats that can't be stored as written
+    ///   trailing-whitespace  whitespace at the end of a line
+    ///   indentation          indentation with both tabs and spaces
+    #[structopt(verbatim_doc_comment)]
+    Check {
+        /// Skip this rule (may be repeated)
+        #[structopt(
+            long,
+            value_name = "rule",
+            number_of_values = 1,
+            possible_values = &lint::RULE_NAMES,
+        )]
+        disable: Vec<Rule>,
+
+        /// Check only this rule, and any others given (may be repeated)
+        #[structopt(
+            long,
+            value_name = "rule",
+            number_of_values = 1,
+            possible_values = &lint::RULE_NAMES,
+        )]
+        only: Vec<Rule>,
+
+        /// How long an inline table may be, in characters
+        #[structopt(long, default_value = "80", value_name = "n")]
+        max_inline_width: usize,
+
+        /// How to print warnings: `human`, or `json` for one JSON object
+        /// per warning
+        #[structopt(
+            long,
+            default_value = "human",
+            possible_values = &["human", "json"],
+        )]
+        format: ErrorFormat,
+
+        /// Paths to the TOML files to check
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Edit the file to set some data (currently, just print modified version)
+    ///
+    /// Read the given TOML file, place the given value at the given query,
+    /// and print the modified document.
+    ///
+    /// To edit several files at once, pass `--file`/`-f` once per file (or
+    /// with a glob pattern), or `--files-from` with a list of paths, in place
+    /// of the path argument.  Each file is then edited in place, and a summary
+    /// printed of which files changed, which already had the value, and which
+    /// could not be edited.
+    #[structopt(
+        verbatim_doc_comment,
+        usage = "toml set [OPTIONS] <path> <query> <value-str>\n    \
+                 toml set [OPTIONS] --file <file>... <query> <value-str>"
+    )]
+    Set {
+        /// Path to the TOML file to read (unless `--file` or `--files-from` is
+        /// given), then the query within the TOML data (e.g. `dependencies.serde`,
+        /// `foo[0].bar`), then the string value to place at that spot (bool,
+        /// array, etc. are TODO)
+        #[structopt(parse(from_os_str), required = true, value_name = "args")]
+        args: Vec<OsString>,
+
+        #[structopt(flatten)]
+        files: FileOpts,
+
+        #[structopt(flatten)]
+        opts: SetOpts,
+    },
+    //
+    // TODO: append/add (name TBD)
+}
+
+#[derive(StructOpt)]
+enum CommentCommand {
+    /// Print the comment on some data in the file
+    ///
+    /// The comment on an item is the comment lines just before it, or with
+    /// `--trailing`, the comment at the end of its line.
+    ///
+    /// Print each line of the comment, without its `#`.  If there's
+    /// no comment, print nothing.
     #[structopt(verbatim_doc_comment)]
     Get {
         /// Path to the TOML file to read
@@ -39,10 +447,14 @@ enum Args {
         query: String,
 
         #[structopt(flatten)]
-        opts: GetOpts,
+        opts: CommentOpts,
     },
 
-    /// Edit the file to set some data (currently, just print modified version)
+    /// Place a comment on some data in the file, and print the modified document
+    ///
+    /// The text replaces any comment already there.  A leading comment
+    /// may have several lines.  If the text is empty, remove the comment.
+    #[structopt(verbatim_doc_comment)]
     Set {
         /// Path to the TOML file to read
         #[structopt(parse(from_os_str))]
@@ -51,11 +463,40 @@ enum Args {
         /// Query within the TOML data (e.g. `dependencies.serde`, `foo[0].bar`)
         query: String,
 
-        /// String value to place at the given spot (bool, array, etc. are TODO)
-        value_str: String, // TODO more forms
+        /// The text of the comment, without `#`
+        text: String,
+
+        #[structopt(flatten)]
+        opts: CommentOpts,
     },
-    //
-    // TODO: append/add (name TBD)
+}
+
+#[derive(StructOpt)]
+struct CommentOpts {
+    /// Use the comment at the end of the item's line, rather than on the
+    /// lines just before it
+    #[structopt(long)]
+    trailing: bool,
+}
+
+#[derive(StructOpt)]
+struct FileOpts {
+    /// Read this file, in place of the path argument (may be repeated, and
+    /// may be a glob pattern like `crates/*/Cargo.toml`)
+    #[structopt(long = "file", short = "f", number_of_values = 1, value_name = "file")]
+    files: Vec<String>,
+
+    /// Read the paths of files, one per line, from this file (`-` for stdin),
+    /// in place of the path argument
+    #[structopt(long, parse(from_os_str), value_name = "list")]
+    files_from: Option<PathBuf>,
+}
+
+impl FileOpts {
+    /// Whether these options name the files, in place of a path argument.
+    fn replace_path_arg(&self) -> bool {
+        !self.files.is_empty() || self.files_from.is_some()
+    }
 }
 
 #[derive(StructOpt)]
@@ -68,77 +509,1284 @@ struct GetOpts {
     // (No effect when the item isn't a string, just like `jq -r`.)
     #[structopt(long, short)]
     raw: bool,
+
+    /// Prefix each result with its file's name (the default with several files)
+    #[structopt(long, short = "H")]
+    with_filename: bool,
+
+    /// Skip files that lack a key, rather than failing
+    #[structopt(long)]
+    skip_missing: bool,
+
+    /// Take each value from the last of the files that has it
+    #[structopt(long)]
+    layered: bool,
+
+    /// Print the file and line each result came from, then a tab, then the result
+    #[structopt(long)]
+    show_origin: bool,
+
+    /// Like `--show-origin`, but print where each result starts and ends
+    /// in the file, as `FILE:LINE:COL-LINE:COL`
+    #[structopt(long, conflicts_with = "output-toml")]
+    show_span: bool,
+
+    /// If the key is missing, print this string value instead of failing
+    #[structopt(long, value_name = "value")]
+    default: Option<String>,
+
+    /// Exit with failure if the result is `false` or an empty table or array
+    #[structopt(long, short = "e")]
+    exit_status: bool,
+
+    /// Fail unless the result has this type; with `path`, expand `~` and
+    /// resolve relative paths against the TOML file's directory
+    #[structopt(
+        long = "type",
+        value_name = "type",
+        possible_values = &["bool", "int", "float", "string", "path"],
+        conflicts_with = "output-toml",
+    )]
+    value_type: Option<ValueType>,
+}
+
+#[derive(Default, StructOpt)]
+struct SetOpts {
+    /// How to write any new tables: as `[a.b]` sections, as inline tables
+    /// like `a = { b = { c = 1 } }`, or as dotted keys like `a.b.c = 1`
+    #[structopt(
+        long,
+        value_name = "style",
+        default_value = "table",
+        possible_values = &["table", "inline", "dotted"],
+    )]
+    style: TableStyle,
+
+    /// How to quote the value: `auto` keeps the quoting of the string being
+    /// replaced; the other styles also make the value a string even if it
+    /// replaces an integer
+    #[structopt(
+        long,
+        value_name = "style",
+        default_value = "auto",
+        possible_values = &["basic", "literal", "multiline", "auto"],
+    )]
+    string_style: StringStyle,
+
+    /// Check the edited document against this JSON Schema, and refuse the
+    /// edit if it breaks the schema in a new way; and write the value as
+    /// the type the schema gives for it, like an integer
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    schema: Option<PathBuf>,
+}
+
+/// How `set` writes the tables it creates, or what `convert` makes of one.
+#[derive(Clone, Copy, Debug, Default)]
+enum TableStyle {
+    #[default]
+    Table,
+    Inline,
+    Dotted,
+}
+
+impl str::FromStr for TableStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(TableStyle::Table),
+            "inline" => Ok(TableStyle::Inline),
+            "dotted" => Ok(TableStyle::Dotted),
+            _ => Err(format!("unknown style: {}", s)),
+        }
+    }
+}
+
+/// How `set` quotes a string value.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum StringStyle {
+    Basic,
+    Literal,
+    Multiline,
+    #[default]
+    Auto,
+}
+
+impl str::FromStr for StringStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "basic" => Ok(StringStyle::Basic),
+            "literal" => Ok(StringStyle::Literal),
+            "multiline" => Ok(StringStyle::Multiline),
+            "auto" => Ok(StringStyle::Auto),
+            _ => Err(format!("unknown string style: {}", s)),
+        }
+    }
+}
+
+/// A type that `get --type` can require the result to have.
+#[derive(Clone, Copy, Debug)]
+enum ValueType {
+    Bool,
+    Int,
+    Float,
+    String,
+    Path,
+}
+
+impl str::FromStr for ValueType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bool" => Ok(ValueType::Bool),
+            "int" => Ok(ValueType::Int),
+            "float" => Ok(ValueType::Float),
+            "string" => Ok(ValueType::String),
+            "path" => Ok(ValueType::Path),
+            _ => Err(format!("unknown type: {}", s)),
+        }
+    }
+}
+
+impl ValueType {
+    fn name(self) -> &'static str {
+        match self {
+            ValueType::Bool => "bool",
+            ValueType::Int => "int",
+            ValueType::Float => "float",
+            ValueType::String => "string",
+            ValueType::Path => "path",
+        }
+    }
+
+    /// The name of the TOML type that values of this type are stored as.
+    fn toml_type_name(self) -> &'static str {
+        match self {
+            ValueType::Bool => "boolean",
+            ValueType::Int => "integer",
+            ValueType::Float => "float",
+            ValueType::String | ValueType::Path => "string",
+        }
+    }
 }
 
 #[derive(Debug, Error)]
 enum CliError {
-    #[error("syntax error in query: {0}")]
-    QuerySyntaxError(String),
+    #[error("syntax error in query: {query}\n{}", describe_query_error(query, err))]
+    QuerySyntaxError { query: String, err: QueryError },
     #[error("numeric index into non-array")]
     NotArray(),
     #[error("array index out of bounds")]
     ArrayIndexOob(),
+    #[error("key not found: {key}")]
+    KeyNotFound {
+        key: String,
+        /// The file, if known, though the message doesn't name it.
+        path: Option<PathBuf>,
+    },
+    #[error("{}: key not found: {key}", path.display())]
+    KeyNotFoundInFile { path: PathBuf, key: String },
+    #[error("{key}: expected {expected}, found {found}")]
+    WrongType {
+        key: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("invalid default for --type={}: {value}", value_type.name())]
+    BadDefault {
+        value_type: ValueType,
+        value: String,
+    },
+    #[error("--output-toml accepts only one query")]
+    MultipleQueriesToml(),
+    #[error("--output-toml accepts only one file")]
+    MultipleFilesToml(),
+    #[error("the path matches more than one file; to edit each in place, pass it with --file")]
+    MultipleFilesSet(),
+    #[error("{0}: can't replace the whole document")]
+    RootDestination(String),
+    #[error("{0}")]
+    Usage(String),
+    #[error("missing argument")]
+    MissingArgument(),
+    #[error("unexpected argument: {0}")]
+    ExtraArgument(String),
+    #[error("query is not valid UTF-8: {0:?}")]
+    NonUtf8Query(OsString),
+    #[error("invalid glob pattern: {0}")]
+    BadGlob(#[from] glob::PatternError),
+    #[error("no files match pattern: {0}")]
+    NoMatchingFiles(String),
+    #[error("{key}: no place for a comment: {reason}")]
+    NoCommentPlace { key: String, reason: &'static str },
+    #[error("a trailing comment must be a single line")]
+    MultilineTrailingComment(),
+    #[error("value can't be written as a literal string: {0:?}")]
+    NotLiteral(String),
+    #[error("{key}: already exists; pass --force to replace it")]
+    DestinationExists { key: String },
+    #[error("can't move {from} into itself, at {to}")]
+    MoveIntoItself { from: String, to: String },
+    #[error("{key}: can't convert: {reason}")]
+    CantConvert { key: String, reason: &'static str },
+    #[error("{}: invalid schema: {message}", path.display())]
+    BadSchema { path: PathBuf, message: String },
+    #[error("edit doesn't match the schema: {0}")]
+    SchemaViolation(String),
+}
+
+/// A failure reading or writing a file.
+#[derive(Debug, Error)]
+#[error("{}: {source}", path.display())]
+struct IoError {
+    path: PathBuf,
+    source: io::Error,
+}
+
+impl IoError {
+    fn wrap(path: &Path) -> impl FnOnce(io::Error) -> IoError + '_ {
+        move |source| IoError {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+/// The 1-based line and column of a byte offset into a file.
+///
+/// The column counts characters, not bytes.
+fn line_col_at(data: &[u8], offset: usize) -> (usize, usize) {
+    let before = &data[..offset];
+    let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+    let line_start = before
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    let column = String::from_utf8_lossy(&before[line_start..])
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+/// A failure to parse a TOML file, located within the file if possible.
+///
+/// Displayed like `FILE:LINE:COL: message`, then the offending line with a
+/// caret under the error, so that editors and CI tools can find the spot.
+#[derive(Debug, Error)]
+#[error("{}:{}", path.display(), self.describe())]
+struct ParseError {
+    path: PathBuf,
+    /// 1-based line and column of the error, if known.
+    line_col: Option<(usize, usize)>,
+    message: String,
+    /// The offending line of the file.
+    source_line: Option<String>,
+}
+
+impl ParseError {
+    fn new(path: &Path, data: &[u8], line_col: Option<(usize, usize)>, message: String) -> Self {
+        let source_line = line_col.and_then(|(line, _)| {
+            let line = data.split(|&b| b == b'\n').nth(line - 1)?;
+            Some(String::from_utf8_lossy(line).trim_end().to_string())
+        });
+        ParseError {
+            path: path.into(),
+            line_col,
+            message,
+            source_line,
+        }
+    }
+
+    fn from_toml(path: &Path, data: &str, err: toml_edit::TomlError) -> Self {
+        let message = err.message().trim_end().lines().collect::<Vec<_>>();
+        let line_col = err
+            .span()
+            .map(|span| line_col_at(data.as_bytes(), span.start));
+        Self::new(path, data.as_bytes(), line_col, message.join("; "))
+    }
+
+    fn from_utf8(path: &Path, data: &[u8], err: str::Utf8Error) -> Self {
+        let line_col = line_col_at(data, err.valid_up_to());
+        Self::new(path, data, Some(line_col), "invalid UTF-8".into())
+    }
+
+    fn describe(&self) -> String {
+        let (line, column) = match self.line_col {
+            Some(line_col) => line_col,
+            None => return format!(" {}", self.message),
+        };
+        let mut out = format!("{}:{}: {}", line, column, self.message);
+        if let Some(source_line) = &self.source_line {
+            let gutter = " ".repeat(line.to_string().len());
+            let indent: String = source_line
+                .chars()
+                .take(column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            out += &format!("\n{gutter} |\n{line} | {source_line}\n{gutter} | {indent}^");
+        }
+        out
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl str::FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("unknown error format: {}", s)),
+        }
+    }
+}
+
+/// A broad classification of errors, for the exit status and for
+/// machine-readable error output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ErrorKind {
+    KeyNotFound,
+    FalseResult,
+    Usage,
+    QuerySyntax,
+    Io,
+    Parse,
+    TypeConflict,
+    Other,
+}
+
+impl ErrorKind {
+    fn of(err: &Error) -> Self {
+        if let Some(err) = err.downcast_ref::<SilentError>() {
+            return match err {
+                SilentError::KeyNotFound { .. } => ErrorKind::KeyNotFound,
+                SilentError::FalseResult()
+                | SilentError::CheckFailed { .. }
+                | SilentError::Invalid { .. }
+                | SilentError::Warnings { .. } => ErrorKind::FalseResult,
+                SilentError::EditsFailed { kind, .. } | SilentError::ChecksFailed { kind, .. } => {
+                    *kind
+                }
+            };
+        }
+        if let Some(err) = err.downcast_ref::<CliError>() {
+            return match err {
+                CliError::KeyNotFound { .. }
+                | CliError::KeyNotFoundInFile { .. }
+                | CliError::ArrayIndexOob() => ErrorKind::KeyNotFound,
+                CliError::QuerySyntaxError { .. } => ErrorKind::QuerySyntax,
+                CliError::MultipleQueriesToml()
+                | CliError::MultipleFilesToml()
+                | CliError::MultipleFilesSet()
+                | CliError::RootDestination(_)
+                | CliError::Usage(_)
+                | CliError::MissingArgument()
+                | CliError::ExtraArgument(_)
+                | CliError::NonUtf8Query(_)
+                | CliError::BadGlob(_)
+                | CliError::NoMatchingFiles(_)
+                | CliError::MultilineTrailingComment()
+                | CliError::NotLiteral(_)
+                | CliError::DestinationExists { .. }
+                | CliError::MoveIntoItself { .. }
+                | CliError::BadSchema { .. }
+                | CliError::BadDefault { .. } => ErrorKind::Usage,
+                CliError::NotArray()
+                | CliError::WrongType { .. }
+                | CliError::NoCommentPlace { .. }
+                | CliError::CantConvert { .. }
+                | CliError::SchemaViolation(_) => ErrorKind::TypeConflict,
+            };
+        }
+        if err.is::<IoError>() || err.is::<glob::GlobError>() {
+            ErrorKind::Io
+        } else if err.is::<ParseError>() {
+            ErrorKind::Parse
+        } else {
+            ErrorKind::Other
+        }
+    }
+
+    /// The exit status for this kind of error, as documented in `EXIT_STATUS_HELP`.
+    fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::KeyNotFound | ErrorKind::FalseResult => 1,
+            ErrorKind::Usage | ErrorKind::QuerySyntax => 2,
+            ErrorKind::Io => 3,
+            ErrorKind::Parse => 4,
+            ErrorKind::TypeConflict => 5,
+            ErrorKind::Other => 6,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ErrorKind::KeyNotFound => "key-not-found",
+            ErrorKind::FalseResult => "false-result",
+            ErrorKind::Usage => "usage",
+            ErrorKind::QuerySyntax => "query-syntax",
+            ErrorKind::Io => "io",
+            ErrorKind::Parse => "parse",
+            ErrorKind::TypeConflict => "type-conflict",
+            ErrorKind::Other => "other",
+        }
+    }
 }
 
 /// An error that should cause a failure exit, but no message on stderr.
 #[derive(Debug, Error)]
 enum SilentError {
     #[error("key not found: {key}")]
-    KeyNotFound { key: String },
+    KeyNotFound {
+        key: String,
+        /// The file, if known.
+        path: Option<PathBuf>,
+    },
+    #[error("result is false or empty")]
+    FalseResult(),
+    #[error("{count} of the files would change")]
+    CheckFailed { count: usize },
+    #[error("{count} of the files don't match the schema")]
+    Invalid { count: usize },
+    #[error("{count} of the files have warnings")]
+    Warnings { count: usize },
+    #[error("failed to edit {count} of the files")]
+    EditsFailed {
+        count: usize,
+        /// The kind of the first failure.
+        kind: ErrorKind,
+    },
+    #[error("failed to check {count} of the files")]
+    ChecksFailed {
+        count: usize,
+        /// The kind of the first failure.
+        kind: ErrorKind,
+    },
 }
 
 fn main() {
-    let args = Args::from_args();
-    let result = match args {
-        Args::Get { path, query, opts } => get(&path, &query, &opts),
-        Args::Set {
+    let args = Args::from_iter_safe(std::env::args_os()).unwrap_or_else(|err| {
+        use structopt::clap::ErrorKind::{HelpDisplayed, VersionDisplayed};
+        match err.kind {
+            HelpDisplayed | VersionDisplayed => err.exit(),
+            _ => {
+                match error_format_from_raw_args() {
+                    // The message is already formatted for the terminal.
+                    ErrorFormat::Human => eprintln!("{}", err.message),
+                    format => report_error(format, &CliError::Usage(err.message).into(), None),
+                }
+                exit(ErrorKind::Usage.exit_code());
+            }
+        }
+    });
+    let error_format = args.error_format;
+    let result = match args.command {
+        Command::Get { args, files, opts } => {
+            split_path_args(&files, args).and_then(|(paths, queries)| get(&paths, &queries, &opts))
+        }
+        Command::Exists { path, query } => exists(&path, &query),
+        Command::List { path, query } => list(&path, query.as_deref().unwrap_or(".")),
+        Command::Keys { path, query } => keys(&path, &query),
+        Command::Type { path, query } => type_(&path, &query),
+        Command::Length { path, query } => length(&path, &query),
+        Command::Comment { command } => match command {
+            CommentCommand::Get { path, query, opts } => comment_get(&path, &query, &opts),
+            CommentCommand::Set {
+                path,
+                query,
+                text,
+                opts,
+            } => comment_set(&path, &query, &text, &opts),
+        },
+        Command::Fmt {
+            paths,
+            check,
+            width,
+        } => fmt_files(&paths, check, &FormatOpts { width }, error_format),
+        Command::Sort {
             path,
             query,
-            value_str,
-        } => set(&path, &query, &value_str),
+            recursive,
+            by,
+            check,
+        } => sort(
+            &path,
+            query.as_deref().unwrap_or("."),
+            recursive,
+            by.as_deref(),
+            check,
+        ),
+        Command::Mv {
+            path,
+            from,
+            to,
+            force,
+        } => mv(&path, &from, &to, force),
+        Command::Cp {
+            src_path,
+            src_query,
+            dst_path,
+            dst_query,
+            force,
+        } => cp(&src_path, &src_query, &dst_path, &dst_query, force),
+        Command::Convert { path, query, to } => convert(&path, &query, to),
+        Command::Validate { schema, paths } => validate_files(&schema, &paths, error_format),
+        Command::Check {
+            disable,
+            only,
+            max_inline_width,
+            format,
+            paths,
+        } => {
+            let rules: Vec<Rule> = Rule::ALL
+                .into_iter()
+                .filter(|rule| only.is_empty() || only.contains(rule))
+                .filter(|rule| !disable.contains(rule))
+                .collect();
+            check_files(&paths, &rules, max_inline_width, format, error_format)
+        }
+        Command::Set { args, files, opts } => {
+            split_path_args(&files, args).and_then(|(paths, args)| {
+                let (query, value_str) = match &args[..] {
+                    [query, value_str] => (query, value_str),
+                    [_] => Err(CliError::MissingArgument())?,
+                    [_, _, extra, ..] => Err(CliError::ExtraArgument(extra.clone()))?,
+                    [] => unreachable!(),
+                };
+                if files.replace_path_arg() {
+                    set_files(&paths, query, value_str, &opts, error_format)
+                } else if paths.len() > 1 {
+                    Err(CliError::MultipleFilesSet())?
+                } else {
+                    set(&paths[0], query, value_str, &opts)
+                }
+            })
+        }
     };
     result.unwrap_or_else(|err| {
-        match err.downcast::<SilentError>() {
-            Ok(_) => {}
-            Err(err) => {
-                eprintln!("toml: {}", err);
+        report_error(error_format, &err, None);
+        exit(ErrorKind::of(&err).exit_code());
+    })
+}
+
+/// Find `--error-format`, for reporting errors from parsing the command line
+/// itself.
+fn error_format_from_raw_args() -> ErrorFormat {
+    let args: Vec<_> = std::env::args_os().collect();
+    for (i, arg) in args.iter().enumerate() {
+        let value = if arg == "--error-format" {
+            args.get(i + 1).and_then(|v| v.to_str())
+        } else {
+            arg.to_str().and_then(|a| a.strip_prefix("--error-format="))
+        };
+        if let Some(Ok(format)) = value.map(str::parse) {
+            return format;
+        }
+    }
+    ErrorFormat::Human
+}
+
+/// Print an error to stderr in the given format.
+///
+/// If the error concerns a particular file, `path` names it, in case the
+/// error itself doesn't.
+///
+/// In the JSON format, the error is a single line holding an object with
+/// `kind`, `message`, `path`, `query`, and `span`.  The `span` is `null`
+/// or an object with `line` and `column` (1-based) into the file, or
+/// `offset` (in bytes) into the query.
+fn report_error(format: ErrorFormat, err: &Error, path: Option<&Path>) {
+    let names_file = err.is::<ParseError>() || err.is::<IoError>();
+    let path = path.filter(|_| !names_file);
+    match format {
+        ErrorFormat::Human => match path {
+            _ if err.is::<SilentError>() => {}
+            Some(path) => eprintln!("toml: {}: {}", path.display(), err),
+            None => eprintln!("toml: {}", err),
+        },
+        ErrorFormat::Json => {
+            if let Some(SilentError::EditsFailed { .. } | SilentError::ChecksFailed { .. }) =
+                err.downcast_ref()
+            {
+                // Each failure was already reported.
+                return;
+            }
+            eprintln!("{}", error_json(err, path));
+        }
+    }
+}
+
+fn error_json<'a>(err: &'a Error, mut path: Option<&'a Path>) -> serde_json::Value {
+    let mut message = err.to_string();
+    let mut query = None;
+    let mut span = None;
+    if let Some(err) = err.downcast_ref::<ParseError>() {
+        message = err.message.clone();
+        path = Some(&err.path);
+        span = err
+            .line_col
+            .map(|(line, column)| serde_json::json!({ "line": line, "column": column }));
+    } else if let Some(err) = err.downcast_ref::<IoError>() {
+        message = err.source.to_string();
+        path = Some(&err.path);
+    } else if let Some(err) = err.downcast_ref::<SilentError>() {
+        if let SilentError::KeyNotFound { key, path: p } = err {
+            query = Some(key);
+            path = p.as_deref().or(path);
+        }
+    } else if let Some(err) = err.downcast_ref::<CliError>() {
+        match err {
+            CliError::KeyNotFound { key, path: p } => {
+                query = Some(key);
+                path = p.as_deref().or(path);
+            }
+            CliError::KeyNotFoundInFile { path: p, key } => {
+                message = format!("key not found: {}", key);
+                path = Some(p);
+                query = Some(key);
+            }
+            CliError::QuerySyntaxError { query: q, err } => {
+                message = format!("expected {}", err.expected);
+                if let Some(hint) = &err.hint {
+                    message += &format!("; {}", hint);
+                }
+                query = Some(q);
+                span = Some(serde_json::json!({ "offset": err.offset }));
             }
+            _ => {}
         }
-        exit(1);
+    }
+    serde_json::json!({
+        "kind": ErrorKind::of(err).name(),
+        "message": message,
+        "path": path,
+        "query": query,
+        "span": span,
     })
 }
 
-fn read_parse(path: &PathBuf) -> Result<Document, Error> {
-    // TODO: better report errors like ENOENT
-    let data = fs::read(path)?;
-    let data = str::from_utf8(&data)?;
-    Ok(data.parse::<Document>()?)
+/// Read a list of paths, one per line, ignoring blank lines.
+fn read_path_list(list: &Path) -> Result<Vec<PathBuf>, Error> {
+    let data = if list == Path::new("-") {
+        let mut data = String::new();
+        io::stdin()
+            .read_to_string(&mut data)
+            .map_err(IoError::wrap(Path::new("<stdin>")))?;
+        data
+    } else {
+        fs::read_to_string(list).map_err(IoError::wrap(list))?
+    };
+    Ok(data
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(PathBuf::from)
+        .collect())
 }
 
-fn get(path: &PathBuf, query: &str, opts: &GetOpts) -> Result<(), Error> {
-    let tpath = parse_query_cli(query)?.0;
-    let doc = read_parse(path)?;
+/// Separate a subcommand's positional arguments into the files to act on
+/// and the remaining arguments.
+///
+/// The files come from `--file` and `--files-from` if given, and otherwise
+/// from the first positional argument.  Either way, glob patterns are expanded.
+fn split_path_args(
+    files: &FileOpts,
+    mut args: Vec<OsString>,
+) -> Result<(Vec<PathBuf>, Vec<String>), Error> {
+    let mut paths = vec![];
+    if !files.replace_path_arg() {
+        if args.is_empty() {
+            Err(CliError::MissingArgument())?;
+        }
+        let path = args.remove(0);
+        match path.to_str() {
+            Some(pattern) => paths.extend(expand_path_pattern(pattern)?),
+            None => paths.push(PathBuf::from(path)),
+        }
+    }
+    for pattern in &files.files {
+        paths.extend(expand_path_pattern(pattern)?);
+    }
+    if let Some(list) = &files.files_from {
+        paths.extend(read_path_list(list)?);
+    }
+    if args.is_empty() {
+        Err(CliError::MissingArgument())?;
+    }
+    let args = args
+        .into_iter()
+        .map(|arg| arg.into_string().map_err(CliError::NonUtf8Query))
+        .collect::<Result<_, _>>()?;
+    Ok((paths, args))
+}
+
+fn expand_path_pattern(pattern: &str) -> Result<Vec<PathBuf>, Error> {
+    // A path that exists is taken literally, even if it looks like a pattern.
+    if Path::new(pattern).exists() || !pattern.contains(['*', '?', '[']) {
+        return Ok(vec![pattern.into()]);
+    }
+    let paths = glob::glob(pattern)
+        .map_err(CliError::BadGlob)?
+        .collect::<Result<Vec<_>, _>>()?;
+    if paths.is_empty() {
+        Err(CliError::NoMatchingFiles(pattern.into()))?;
+    }
+    Ok(paths)
+}
+
+fn read_parse(path: &Path) -> Result<DocumentMut, Error> {
+    parse_doc(path, &read_utf8(path)?)
+}
+
+/// Parse the contents of the file at `path`, for editing.
+fn parse_doc(path: &Path, data: &str) -> Result<DocumentMut, Error> {
+    // Parsing the borrowed string, then converting, saves copying the file.
+    let doc = ImDocument::parse(data).map_err(|err| ParseError::from_toml(path, data, err))?;
+    Ok(doc.into_mut())
+}
+
+/// Like `read_parse`, but keep track of where in the file each item came from.
+///
+/// The result is read-only; but the items can be printed as JSON, and their
+/// spans looked up with `item_span`.
+fn read_parse_spanned(path: &Path) -> Result<ImDocument<String>, Error> {
+    let data = read_utf8(path)?;
+    Ok(ImDocument::parse(data.clone()).map_err(|err| ParseError::from_toml(path, &data, err))?)
+}
+
+fn read_utf8(path: &Path) -> Result<String, Error> {
+    let data = fs::read(path).map_err(IoError::wrap(path))?;
+    Ok(String::from_utf8(data)
+        .map_err(|err| ParseError::from_utf8(path, err.as_bytes(), err.utf8_error()))?)
+}
+
+/// Replace the contents of the file.
+///
+/// The new contents go to a temporary file in the same directory, which is
+/// then renamed over the file; so if the write is interrupted, the file is
+/// left as it was rather than cut short.  A symlink is followed, and the
+/// file keeps its permissions.
+fn write_in_place(path: &Path, data: &str) -> Result<(), IoError> {
+    let target = fs::canonicalize(path).map_err(IoError::wrap(path))?;
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = target.with_file_name(format!(".{}.{}.tmp", name, process::id()));
+    let result = fs::write(&tmp, data)
+        .and_then(|()| fs::set_permissions(&tmp, fs::metadata(&target)?.permissions()))
+        .and_then(|()| fs::rename(&tmp, &target));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result.map_err(IoError::wrap(path))
+}
+
+fn get(paths: &[PathBuf], queries: &[String], opts: &GetOpts) -> Result<(), Error> {
+    let tpaths = queries
+        .iter()
+        .map(|query| Ok(parse_query_cli(query)?.0))
+        .collect::<Result<Vec<_>, Error>>()?;
 
     if opts.output_toml {
-        print_toml_fragment(&doc, &tpath);
+        if tpaths.len() > 1 {
+            Err(CliError::MultipleQueriesToml())?;
+        }
+        if paths.len() > 1 {
+            Err(CliError::MultipleFilesToml())?;
+        }
+        let mut doc = read_parse(&paths[0])?;
+        if let Some(default) = &opts.default {
+            if walk_tpath(doc.as_item(), &tpaths[0]).is_none() {
+                set_in_doc(&mut doc, &tpaths[0], default, &SetOpts::default(), None)?;
+            }
+        }
+        let item = walk_tpath(doc.as_item(), &tpaths[0])
+            .ok_or_else(|| key_not_found(Some(&paths[0]), false, queries, &queries[0]))?;
+        print_toml_fragment(&doc, &tpaths[0]);
+        if opts.exit_status && is_false_or_empty(item) {
+            Err(SilentError::FalseResult())?;
+        }
         return Ok(());
     }
 
+    let spanned = opts.show_origin || opts.show_span;
+    let mut docs = Vec::with_capacity(paths.len());
+    for path in paths {
+        let doc = if spanned {
+            read_parse_spanned(path).map(GetDoc::Spanned)
+        } else {
+            read_parse(path).map(GetDoc::Plain)
+        };
+        match doc {
+            Ok(doc) => docs.push((path, doc)),
+            // A missing layer of configuration is normal, like `git config`.
+            Err(err) if opts.layered && is_not_found(&err) => {}
+            Err(err) => Err(err)?,
+        }
+    }
+
+    // Look up every query before printing anything, so that a missing key
+    // doesn't leave partial output behind.
+    let mut lines = vec![];
+    let mut any_false = false;
+    if opts.layered {
+        let mut results = Vec::with_capacity(tpaths.len());
+        for (query, tpath) in queries.iter().zip(&tpaths) {
+            let mut found = None;
+            for (path, doc) in docs.iter().rev() {
+                found = lookup(doc, path, query, tpath, opts)?;
+                if found.is_some() {
+                    break;
+                }
+            }
+            // A relative `--type=path` default is taken relative to the
+            // highest-precedence file.
+            let last_path = paths.last().map(PathBuf::as_path);
+            match (found, &opts.default) {
+                (Some(found), _) => results.push(found),
+                (None, Some(default)) => results.push(lookup_default(default, last_path, opts)?),
+                (None, None) => Err(key_not_found(None, false, queries, query))?,
+            }
+        }
+        any_false |= results.iter().any(|found| is_false_or_empty(&found.item));
+        lines.extend(format_results(&results, opts)?);
+    } else {
+        let with_filename =
+            (opts.with_filename || paths.len() > 1) && !opts.show_origin && !opts.show_span;
+        for (path, doc) in &docs {
+            let mut results = Vec::with_capacity(tpaths.len());
+            for (query, tpath) in queries.iter().zip(&tpaths) {
+                match (lookup(doc, path, query, tpath, opts)?, &opts.default) {
+                    (Some(found), _) => results.push(found),
+                    (None, Some(default)) => {
+                        results.push(lookup_default(default, Some(path), opts)?)
+                    }
+                    (None, None) if opts.skip_missing => break,
+                    (None, None) => {
+                        let name_file = paths.len() > 1;
+                        Err(key_not_found(Some(path), name_file, queries, query))?
+                    }
+                }
+            }
+            if results.len() < tpaths.len() {
+                continue;
+            }
+            any_false |= results.iter().any(|found| is_false_or_empty(&found.item));
+
+            for line in format_results(&results, opts)? {
+                if with_filename {
+                    lines.push(format!("{}:{}", path.display(), line));
+                } else {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+
+    if lines.is_empty() {
+        // Every file was skipped for lacking some key.
+        Err(SilentError::KeyNotFound {
+            key: queries.join(" "),
+            path: None,
+        })?;
+    }
+    for line in lines {
+        println!("{}", line);
+    }
+    if opts.exit_status && any_false {
+        Err(SilentError::FalseResult())?;
+    }
+    Ok(())
+}
+
+fn is_not_found(err: &Error) -> bool {
+    matches!(err.downcast_ref::<IoError>(),
+             Some(err) if err.source.kind() == io::ErrorKind::NotFound)
+}
+
+/// A result of `get`, and where it came from if `--show-origin`.
+struct Found {
+    item: Item,
+    origin: Option<String>,
+}
+
+/// A file as read for `get`: with spans only if they're to be printed.
+enum GetDoc {
+    Plain(DocumentMut),
+    Spanned(ImDocument<String>),
+}
+
+/// Look up one query for `get`, applying any `--type`.
+fn lookup(
+    doc: &GetDoc,
+    path: &Path,
+    query: &str,
+    tpath: &[TpathSegment],
+    opts: &GetOpts,
+) -> Result<Option<Found>, Error> {
+    let root = match doc {
+        GetDoc::Plain(doc) => doc.as_item(),
+        GetDoc::Spanned(doc) => doc.as_item(),
+    };
+    let item = match walk_tpath(root, tpath) {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    let item = match opts.value_type {
+        Some(ty) => check_type(item, ty, path, query)?,
+        None => item.clone(),
+    };
+    let origin = (opts.show_origin || opts.show_span).then(|| {
+        let (span, raw) = match doc {
+            GetDoc::Spanned(doc) => match item_span(root, tpath) {
+                Some(span) => (span, doc.raw()),
+                None => return path.display().to_string(),
+            },
+            GetDoc::Plain(_) => return path.display().to_string(),
+        };
+        let (line, col) = line_col_at(raw.as_bytes(), span.start);
+        if !opts.show_span {
+            return format!("{}:{}", path.display(), line);
+        }
+        // The end is inclusive: the position of the item's last character.
+        let last = raw[span.clone()]
+            .char_indices()
+            .next_back()
+            .map_or(span.start, |(i, _)| span.start + i);
+        let (end_line, end_col) = line_col_at(raw.as_bytes(), last);
+        format!(
+            "{}:{}:{}-{}:{}",
+            path.display(),
+            line,
+            col,
+            end_line,
+            end_col
+        )
+    });
+    Ok(Some(Found { item, origin }))
+}
+
+fn lookup_default(default: &str, path: Option<&Path>, opts: &GetOpts) -> Result<Found, Error> {
+    let path = path.unwrap_or_else(|| Path::new(""));
+    Ok(Found {
+        item: parse_default(default, opts.value_type, path)?,
+        origin: opts.show_origin.then(|| "(default)".into()),
+    })
+}
+
+/// Where in the file the data at this tpath came from, as a byte range.
+///
+/// For data under a key, this runs from the key to the end of the data.
+/// A table defined only through dotted keys, like `a` in `a.b = 1`, has no
+/// span of its own; it runs from its first key to the end of its last value.
+/// This requires an `ImDocument`, as from `read_parse_spanned`.
+fn item_span(root: &Item, tpath: &[TpathSegment]) -> Option<Range<usize>> {
+    let item = walk_tpath(root, tpath)?;
+    let mut span = item.span().or_else(|| contents_span(item));
+    if let Some((TpathSegment::Name(n), parent)) = tpath.split_last() {
+        let parent = walk_tpath(root, parent)?.as_table_like()?;
+        if let Some(key_span) = parent.key(n).and_then(|key| key.span()) {
+            span = Some(span_union(span, key_span));
+        }
+    }
+    span
+}
+
+/// The byte range covering all the keys and values within a table.
+fn contents_span(item: &Item) -> Option<Range<usize>> {
+    let table = item.as_table_like()?;
+    let mut span = None;
+    for (key, child) in table.iter() {
+        let key_span = table.key(key).and_then(|key| key.span());
+        let child_span = child.span().or_else(|| contents_span(child));
+        for s in key_span.into_iter().chain(child_span) {
+            span = Some(span_union(span, s));
+        }
+    }
+    span
+}
+
+fn span_union(a: Option<Range<usize>>, b: Range<usize>) -> Range<usize> {
+    match a {
+        Some(a) => a.start.min(b.start)..a.end.max(b.end),
+        None => b,
+    }
+}
+
+/// Check the result of `get --type`, and put it in canonical form.
+fn check_type(item: &Item, ty: ValueType, path: &Path, query: &str) -> Result<Item, CliError> {
+    let matches = match ty {
+        ValueType::Bool => item.is_bool(),
+        ValueType::Int => item.is_integer(),
+        ValueType::Float => item.is_float(),
+        ValueType::String | ValueType::Path => item.is_str(),
+    };
+    if !matches {
+        return Err(CliError::WrongType {
+            key: query.into(),
+            expected: ty.toml_type_name(),
+            found: item_type_name(item),
+        });
+    }
+    match (ty, item.as_str()) {
+        (ValueType::Path, Some(s)) => Ok(value(resolve_path(s, path))),
+        _ => Ok(item.clone()),
+    }
+}
+
+/// Interpret the `get --default` value, according to any `--type`.
+fn parse_default(default: &str, ty: Option<ValueType>, path: &Path) -> Result<Item, CliError> {
+    let bad_default = |value_type| CliError::BadDefault {
+        value_type,
+        value: default.into(),
+    };
+    Ok(match ty {
+        None | Some(ValueType::String) => value(default),
+        Some(ValueType::Path) => value(resolve_path(default, path)),
+        Some(ty @ ValueType::Bool) => value(default.parse::<bool>().map_err(|_| bad_default(ty))?),
+        Some(ty @ ValueType::Int) => value(default.parse::<i64>().map_err(|_| bad_default(ty))?),
+        Some(ty @ ValueType::Float) => value(default.parse::<f64>().map_err(|_| bad_default(ty))?),
+    })
+}
+
+/// Expand `~` to the home directory, and resolve a relative path against
+/// the directory of the TOML file it came from.
+fn resolve_path(s: &str, toml_path: &Path) -> String {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let resolved = match (s.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => {
+            let dir = toml_path.parent().unwrap_or_else(|| Path::new(""));
+            dir.join(s)
+        }
+    };
+    resolved.to_string_lossy().into_owned()
+}
+
+/// The name of the TOML type of this item, as in the TOML spec.
+fn item_type_name(item: &Item) -> &'static str {
+    match item {
+        Item::None => "none",
+        Item::Value(Value::String(_)) => "string",
+        Item::Value(Value::Integer(_)) => "integer",
+        Item::Value(Value::Float(_)) => "float",
+        Item::Value(Value::Boolean(_)) => "boolean",
+        Item::Value(Value::Datetime(d)) => {
+            let d = d.value();
+            match (d.date, d.time, d.offset) {
+                (Some(_), Some(_), Some(_)) => "offset-datetime",
+                (Some(_), Some(_), None) => "local-datetime",
+                (Some(_), None, _) => "local-date",
+                (None, _, _) => "local-time",
+            }
+        }
+        Item::Value(Value::Array(_)) => "array",
+        Item::Value(Value::InlineTable(_)) => "inline-table",
+        Item::Table(_) => "table",
+        Item::ArrayOfTables(_) => "array-of-tables",
+    }
+}
+
+/// Whether `get --exit-status` should treat this result as a failure,
+/// like `jq -e`.
+fn is_false_or_empty(item: &Item) -> bool {
+    match item {
+        Item::None => true,
+        Item::Value(Value::Boolean(b)) => !b.value(),
+        Item::Value(Value::Array(a)) => a.is_empty(),
+        Item::Value(Value::InlineTable(t)) => t.is_empty(),
+        Item::Value(_) => false,
+        Item::Table(t) => t.is_empty(),
+        Item::ArrayOfTables(a) => a.is_empty(),
+    }
+}
+
+/// Read the file, and act on the data at the given query.
+///
+/// If the data is missing, fail with no message, like `get`.
+fn with_item<T>(
+    path: &Path,
+    query: &str,
+    f: impl FnOnce(&Item) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let tpath = parse_query_cli(query)?.0;
+    let doc = read_parse(path)?;
     let item = walk_tpath(doc.as_item(), &tpath);
-    let item = item.ok_or(SilentError::KeyNotFound { key: query.into() })?;
+    f(item.ok_or(SilentError::KeyNotFound {
+        key: query.into(),
+        path: Some(path.into()),
+    })?)
+}
+
+fn exists(path: &Path, query: &str) -> Result<(), Error> {
+    with_item(path, query, |_| Ok(()))
+}
+
+fn list(path: &Path, query: &str) -> Result<(), Error> {
+    let mut tpath = parse_query_cli(query)?.0;
+    with_item(path, query, |item| {
+        list_item(item, &mut tpath);
+        Ok(())
+    })
+}
+
+fn list_item(item: &Item, tpath: &mut Vec<TpathSegment>) {
+    match item {
+        Item::None => {}
+        Item::Value(v) => list_value(v, tpath),
+        Item::Table(t) => list_table(t, tpath),
+        Item::ArrayOfTables(a) if a.is_empty() => println!("{} = []", format_tpath(tpath)),
+        Item::ArrayOfTables(a) => {
+            for (i, t) in a.iter().enumerate() {
+                tpath.push(TpathSegment::Num(i));
+                list_table(t, tpath);
+                tpath.pop();
+            }
+        }
+    }
+}
+
+fn list_table(table: &Table, tpath: &mut Vec<TpathSegment>) {
+    if table.is_empty() {
+        println!("{} = {{}}", format_tpath(tpath));
+    }
+    for (k, v) in table.iter() {
+        tpath.push(TpathSegment::Name(k.into()));
+        list_item(v, tpath);
+        tpath.pop();
+    }
+}
+
+fn list_value(value: &Value, tpath: &mut Vec<TpathSegment>) {
+    match value {
+        Value::Array(a) if !a.is_empty() => {
+            for (i, v) in a.iter().enumerate() {
+                tpath.push(TpathSegment::Num(i));
+                list_value(v, tpath);
+                tpath.pop();
+            }
+        }
+        Value::InlineTable(t) if !t.is_empty() => {
+            for (k, v) in t.iter() {
+                tpath.push(TpathSegment::Name(k.into()));
+                list_value(v, tpath);
+                tpath.pop();
+            }
+        }
+        // A multi-line string is rewritten on one line, to keep to one line
+        // per value.
+        Value::String(s) if s.display_repr().contains('\n') => {
+            let value = encode_basic_string(s.value(), false);
+            println!("{} = {}", format_tpath(tpath), value);
+        }
+        _ => {
+            // Print the value as written, but without surrounding whitespace
+            // and comments.
+            let value = value.clone().decorated("", "");
+            println!("{} = {}", format_tpath(tpath), value);
+        }
+    }
+}
 
-    if opts.raw {
+fn keys(path: &Path, query: &str) -> Result<(), Error> {
+    with_item(path, query, |item| {
+        let keys: Vec<&str> = match item {
+            Item::Table(t) => t.iter().map(|(k, _)| k).collect(),
+            Item::Value(Value::InlineTable(t)) => t.iter().map(|(k, _)| k).collect(),
+            _ => Err(CliError::WrongType {
+                key: query.into(),
+                expected: "table",
+                found: item_type_name(item),
+            })?,
+        };
+        for key in keys {
+            println!("{}", key);
+        }
+        Ok(())
+    })
+}
+
+fn type_(path: &Path, query: &str) -> Result<(), Error> {
+    with_item(path, query, |item| {
+        println!("{}", item_type_name(item));
+        Ok(())
+    })
+}
+
+fn length(path: &Path, query: &str) -> Result<(), Error> {
+    with_item(path, query, |item| {
+        let len = match item {
+            Item::Table(t) => t.len(),
+            Item::ArrayOfTables(a) => a.len(),
+            Item::Value(Value::InlineTable(t)) => t.len(),
+            Item::Value(Value::Array(a)) => a.len(),
+            _ => Err(CliError::WrongType {
+                key: query.into(),
+                expected: "array or table",
+                found: item_type_name(item),
+            })?,
+        };
+        println!("{}", len);
+        Ok(())
+    })
+}
+
+fn key_not_found(path: Option<&Path>, name_file: bool, queries: &[String], query: &str) -> Error {
+    let key = query.into();
+    let path = path.map(PathBuf::from);
+    match path {
+        Some(path) if name_file => CliError::KeyNotFoundInFile { path, key }.into(),
+        _ if queries.len() > 1 => CliError::KeyNotFound { key, path }.into(),
+        // Like `git config`, a single missing key fails without comment.
+        _ => SilentError::KeyNotFound { key, path }.into(),
+    }
+}
+
+/// Format the results of a `get` for output, one string per line.
+fn format_results(results: &[Found], opts: &GetOpts) -> Result<Vec<String>, Error> {
+    if opts.show_origin || opts.show_span {
+        results
+            .iter()
+            .map(|found| {
+                let origin = found.origin.as_deref().unwrap_or_default();
+                Ok(format!(
+                    "{}\t{}",
+                    origin,
+                    format_item(&found.item, opts.raw)?
+                ))
+            })
+            .collect()
+    } else if results.len() == 1 || opts.raw {
+        results
+            .iter()
+            .map(|found| format_item(&found.item, opts.raw))
+            .collect()
+    } else {
+        let items: Vec<_> = results.iter().map(|found| JsonItem(&found.item)).collect();
+        Ok(vec![serde_json::to_string(&items)?])
+    }
+}
+
+fn format_item(item: &Item, raw: bool) -> Result<String, Error> {
+    if raw {
         if let Item::Value(Value::String(s)) = item {
-            println!("{}", s.value());
-            return Ok(());
+            return Ok(s.value().clone());
         }
     }
 
-    println!("{}", serde_json::to_string(&JsonItem(item))?);
-    Ok(())
+    Ok(serde_json::to_string(&JsonItem(item))?)
 }
 
-fn print_toml_fragment(doc: &Document, tpath: &[TpathSegment]) {
+fn print_toml_fragment(doc: &DocumentMut, tpath: &[TpathSegment]) {
     use TpathSegment::{Name, Num};
 
     let mut item = doc.as_item();
@@ -179,17 +1827,760 @@ fn print_toml_fragment(doc: &Document, tpath: &[TpathSegment]) {
             _ => panic!("UNIMPLEMENTED: --output-toml inside inline data"), // TODO
         }
     }
-    let doc = Document::from(item.into_table().unwrap());
+    let doc = DocumentMut::from(item.into_table().unwrap());
     print!("{}", doc);
 }
 
-fn set(path: &PathBuf, query: &str, value_str: &str) -> Result<(), Error> {
+fn set(path: &Path, query: &str, value_str: &str, opts: &SetOpts) -> Result<(), Error> {
+    let tpath = parse_dest_query_cli(query)?;
+    let schema = opts.schema.as_deref().map(Schema::load).transpose()?;
+    let mut doc = read_parse(path)?;
+    set_in_doc(&mut doc, &tpath, value_str, opts, schema.as_ref())?;
+
+    print!("{}", doc);
+    Ok(())
+}
+
+/// Make the same edit to each of several files, writing them back in place.
+fn set_files(
+    paths: &[PathBuf],
+    query: &str,
+    value_str: &str,
+    opts: &SetOpts,
+    error_format: ErrorFormat,
+) -> Result<(), Error> {
+    let tpath = parse_dest_query_cli(query)?;
+    let schema = opts.schema.as_deref().map(Schema::load).transpose()?;
+    let types = schema
+        .as_ref()
+        .map_or(vec![], |schema| schema.types_at(&tpath));
+
+    let (mut changed, mut unchanged) = (0, 0);
+    let result = for_each_file(paths, true, error_format, |path| {
+        let mut doc = read_parse(path)?;
+        let existing = walk_tpath(doc.as_item(), &tpath);
+        if existing.is_some_and(|item| has_value(item, value_str, opts, &types)) {
+            unchanged += 1;
+            println!("unchanged: {}", path.display());
+            return Ok(());
+        }
+        set_in_doc(&mut doc, &tpath, value_str, opts, schema.as_ref())?;
+        write_in_place(path, &doc.to_string())?;
+        changed += 1;
+        println!("changed: {}", path.display());
+        Ok(())
+    });
+    let failed = paths.len() - changed - unchanged;
+    println!("{changed} changed, {unchanged} unchanged, {failed} failed");
+    result
+}
+
+/// Run `f` on each file in turn, reporting a failure on one and going on to
+/// the next.  If any failed, the result is an error giving how many, and the
+/// kind of the first failure; `edits` says whether `f` was to edit the file,
+/// or only to check it.
+fn for_each_file(
+    paths: &[PathBuf],
+    edits: bool,
+    error_format: ErrorFormat,
+    mut f: impl FnMut(&Path) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut failed = 0;
+    let mut failure_kind = None;
+    for path in paths {
+        if let Err(err) = f(path) {
+            failed += 1;
+            failure_kind.get_or_insert(ErrorKind::of(&err));
+            report_error(error_format, &err, Some(path));
+        }
+    }
+    match failure_kind {
+        Some(kind) if edits => Err(SilentError::EditsFailed {
+            count: failed,
+            kind,
+        })?,
+        Some(kind) => Err(SilentError::ChecksFailed {
+            count: failed,
+            kind,
+        })?,
+        None => Ok(()),
+    }
+}
+
+/// Format each file, or with `check`, print a diff of how it would change.
+fn fmt_files(
+    paths: &[PathBuf],
+    check: bool,
+    opts: &FormatOpts,
+    error_format: ErrorFormat,
+) -> Result<(), Error> {
+    let mut unformatted = 0;
+    for_each_file(paths, !check, error_format, |path| {
+        let original = read_utf8(path)?;
+        let formatted = format_document(&mut parse_doc(path, &original)?, opts);
+        if rewrite(path, &original, &formatted, check)? {
+            unformatted += 1;
+        }
+        Ok(())
+    })?;
+    if check && unformatted > 0 {
+        Err(SilentError::CheckFailed { count: unformatted })?;
+    }
+    Ok(())
+}
+
+/// Check each file against the schema, printing where it doesn't match.
+fn validate_files(
+    schema_path: &Path,
+    paths: &[PathBuf],
+    error_format: ErrorFormat,
+) -> Result<(), Error> {
+    let schema = Schema::load(schema_path)?;
+    let mut invalid = 0;
+    for_each_file(paths, false, error_format, |path| {
+        let doc = read_parse_spanned(path)?;
+        let mut violations: Vec<_> = schema
+            .violations(doc.as_item())
+            .into_iter()
+            .map(|v| {
+                // Data with no span, as for a missing key, is placed at
+                // the nearest item around it that has one, short of the
+                // whole document.
+                let offset = (1..=v.tpath.len())
+                    .rev()
+                    .find_map(|n| item_span(doc.as_item(), &v.tpath[..n]))
+                    .map(|span| span.start);
+                (offset, v)
+            })
+            .collect();
+        violations.sort_by_key(|(offset, _)| *offset);
+        for (offset, v) in &violations {
+            let position = offset.map_or(String::new(), |offset| {
+                let (line, col) = line_col_at(doc.raw().as_bytes(), offset);
+                format!(":{}:{}", line, col)
+            });
+            let query = format_tpath(&v.tpath);
+            println!("{}{}: {}: {}", path.display(), position, query, v.message);
+        }
+        if !violations.is_empty() {
+            invalid += 1;
+        }
+        Ok(())
+    })?;
+    if invalid > 0 {
+        Err(SilentError::Invalid { count: invalid })?;
+    }
+    Ok(())
+}
+
+/// Lint each file, and print the warnings in the given format.
+fn check_files(
+    paths: &[PathBuf],
+    rules: &[Rule],
+    max_inline_width: usize,
+    format: ErrorFormat,
+    error_format: ErrorFormat,
+) -> Result<(), Error> {
+    let mut warned = 0;
+    for_each_file(paths, false, error_format, |path| {
+        let doc = read_parse_spanned(path)?;
+        let warnings = lint::check(&doc, rules, max_inline_width);
+        for w in &warnings {
+            let (line, column) = line_col_at(doc.raw().as_bytes(), w.offset);
+            match format {
+                ErrorFormat::Human => println!(
+                    "{}:{}:{}: {}: {}",
+                    path.display(),
+                    line,
+                    column,
+                    w.rule.name(),
+                    w.message
+                ),
+                ErrorFormat::Json => println!(
+                    "{}",
+                    serde_json::json!({
+                        "path": path,
+                        "span": { "line": line, "column": column },
+                        "rule": w.rule.name(),
+                        "message": w.message,
+                    })
+                ),
+            }
+        }
+        if !warnings.is_empty() {
+            warned += 1;
+        }
+        Ok(())
+    })?;
+    if warned > 0 {
+        Err(SilentError::Warnings { count: warned })?;
+    }
+    Ok(())
+}
+
+/// Sort the table or array at the query, or with `check`, print a diff of
+/// how the file would change.
+fn sort(
+    path: &Path,
+    query: &str,
+    recursive: bool,
+    by: Option<&str>,
+    check: bool,
+) -> Result<(), Error> {
+    let tpath = parse_query_cli(query)?.0;
+    let by = by.map(parse_query_cli).transpose()?.map(|q| q.0);
+    let original = read_utf8(path)?;
+    let mut doc = parse_doc(path, &original)?;
+
+    let item = walk_tpath_mut(doc.as_item_mut(), &tpath)
+        .filter(|item| !item.is_none())
+        .ok_or_else(|| SilentError::KeyNotFound {
+            key: query.into(),
+            path: Some(path.into()),
+        })?;
+    let wrong_type = |expected, found| CliError::WrongType {
+        key: query.into(),
+        expected,
+        found,
+    };
+    match (item, &by) {
+        (Item::Table(t), None) => sort::sort_table(t, recursive),
+        (Item::Value(Value::InlineTable(t)), None) => sort::sort_inline_table(t, recursive),
+        (Item::Value(Value::Array(a)), None) => sort::sort_array(a)
+            .map_err(|v| wrong_type("array of strings, numbers, etc. (or tables, with --by)", v))?,
+        (Item::Value(Value::Array(a)), Some(by)) => {
+            sort::sort_array_by(a, by).map_err(|v| wrong_type("array of tables", v))?
+        }
+        (Item::ArrayOfTables(a), Some(by)) => sort::sort_array_of_tables_by(a, by),
+        (Item::ArrayOfTables(_), None) => Err(CliError::Usage(
+            "to sort an array of tables, give the key to sort by with --by".into(),
+        ))?,
+        (item, None) => Err(wrong_type("table or array", item_type_name(item)))?,
+        (item, Some(_)) => Err(wrong_type("array of tables", item_type_name(item)))?,
+    }
+
+    if rewrite(path, &original, &doc.to_string(), check)? && check {
+        Err(SilentError::CheckFailed { count: 1 })?;
+    }
+    Ok(())
+}
+
+/// Write the new text to the file if it differs, or with `check`, print a
+/// diff instead.  Return whether it differs.
+fn rewrite(path: &Path, original: &str, new: &str, check: bool) -> Result<bool, Error> {
+    if new == original {
+        return Ok(false);
+    }
+    if check {
+        let path = path.display().to_string();
+        let diff = similar::TextDiff::from_lines(original, new);
+        print!("{}", diff.unified_diff().header(&path, &path));
+    } else {
+        write_in_place(path, new)?;
+    }
+    Ok(true)
+}
+
+/// Move the data at one query to another, with its comments.
+fn mv(path: &Path, from: &str, to: &str, force: bool) -> Result<(), Error> {
+    let from_tpath = parse_query_cli(from)?.0;
+    let to_tpath = parse_dest_query_cli(to)?;
+    if to_tpath.starts_with(&from_tpath) {
+        Err(CliError::MoveIntoItself {
+            from: from.into(),
+            to: to.into(),
+        })?;
+    }
+    let mut doc = read_parse(path)?;
+    check_destination(&doc, &to_tpath, to, force)?;
+
+    let (item, key_decor) =
+        copy_from_doc(&doc, &from_tpath).ok_or_else(|| SilentError::KeyNotFound {
+            key: from.into(),
+            path: Some(path.into()),
+        })?;
+    remove_from_doc(&mut doc, &from_tpath);
+    put_in_doc(&mut doc, &to_tpath, item, key_decor)?;
+
+    print!("{}", doc);
+    Ok(())
+}
+
+/// Copy the data at a query in one file to a query in another (or the
+/// same) file, with its formatting.
+fn cp(
+    src_path: &Path,
+    src_query: &str,
+    dst_path: &Path,
+    dst_query: &str,
+    force: bool,
+) -> Result<(), Error> {
+    let src_tpath = parse_query_cli(src_query)?.0;
+    let dst_tpath = parse_dest_query_cli(dst_query)?;
+    let src = read_parse(src_path)?;
+    let (item, key_decor) =
+        copy_from_doc(&src, &src_tpath).ok_or_else(|| CliError::KeyNotFoundInFile {
+            path: src_path.into(),
+            key: src_query.into(),
+        })?;
+    let mut doc = read_parse(dst_path)?;
+    check_destination(&doc, &dst_tpath, dst_query, force)?;
+    put_in_doc(&mut doc, &dst_tpath, item, key_decor)?;
+
+    print!("{}", doc);
+    Ok(())
+}
+
+fn check_destination(
+    doc: &DocumentMut,
+    tpath: &[TpathSegment],
+    query: &str,
+    force: bool,
+) -> Result<(), CliError> {
+    if walk_tpath(doc.as_item(), tpath).is_some_and(|item| !item.is_none()) && !force {
+        return Err(CliError::DestinationExists { key: query.into() });
+    }
+    Ok(())
+}
+
+/// Copy the data at the tpath, along with the decor of its key, which holds
+/// the comment before a key/value pair.
+fn copy_from_doc(doc: &DocumentMut, tpath: &[TpathSegment]) -> Option<(Item, Option<Decor>)> {
+    let (last, parent_tpath) = tpath.split_last()?;
+    let parent = walk_tpath(doc.as_item(), parent_tpath)?;
+    match (last, parent) {
+        (TpathSegment::Name(n), Item::Value(Value::InlineTable(table))) => {
+            // The spacing within an inline table only fits there.
+            let mut value = table.get(n)?.clone();
+            value.decor_mut().clear();
+            Some((Item::Value(value), None))
+        }
+        (TpathSegment::Name(n), parent) => {
+            let table = parent.as_table_like()?;
+            let (key, item) = table.get_key_value(n).filter(|(_, item)| !item.is_none())?;
+            Some((item.clone(), Some(key.leaf_decor().clone())))
+        }
+        (TpathSegment::Num(n), Item::ArrayOfTables(array)) => {
+            Some((Item::Table(array.get(*n)?.clone()), None))
+        }
+        (TpathSegment::Num(n), Item::Value(Value::Array(array))) => {
+            // Likewise the element's whitespace and comments.
+            let mut value = array.get(*n)?.clone();
+            value.decor_mut().clear();
+            Some((Item::Value(value), None))
+        }
+        _ => None,
+    }
+}
+
+/// Remove the data at the tpath, if it's there.
+fn remove_from_doc(doc: &mut DocumentMut, tpath: &[TpathSegment]) {
+    let Some((last, parent_tpath)) = tpath.split_last() else {
+        return;
+    };
+    match (last, walk_tpath_mut(doc.as_item_mut(), parent_tpath)) {
+        (TpathSegment::Name(n), Some(parent)) => {
+            if let Some(table) = parent.as_table_like_mut() {
+                table.remove(n);
+            }
+        }
+        (TpathSegment::Num(n), Some(Item::ArrayOfTables(array))) if *n < array.len() => {
+            array.remove(*n);
+        }
+        (TpathSegment::Num(n), Some(Item::Value(Value::Array(array)))) if *n < array.len() => {
+            array.remove(*n);
+        }
+        _ => {}
+    }
+}
+
+/// Put the data at the tpath, creating tables on the way as `set` does, and
+/// give its key the decor.
+///
+/// The data is converted to the form the place calls for.  Within an inline
+/// table or array, or in place of an inline table or array, a table becomes
+/// an inline table, and an array of tables an array of inline tables.  In
+/// place of a table or array of tables, it goes the other way.
+fn put_in_doc(
+    doc: &mut DocumentMut,
+    tpath: &[TpathSegment],
+    item: Item,
+    key_decor: Option<Decor>,
+) -> Result<(), Error> {
+    if tpath.is_empty() {
+        Err(CliError::RootDestination(format_tpath(tpath)))?;
+    }
+    let (slot, inline) = place_in_doc(doc, tpath, &SetOpts::default())?;
+    let inline = inline || matches!(slot, Item::Value(Value::InlineTable(_) | Value::Array(_)));
+    *slot = if inline {
+        inline_item(item)
+    } else if matches!(slot, Item::Table(_) | Item::ArrayOfTables(_)) {
+        without_positions(standard_item(item))
+    } else {
+        without_positions(item)
+    };
+    // A table header wherever it lands gets a blank line before it, like
+    // the others.
+    let header = match slot {
+        Item::Table(t) if !t.is_implicit() && !t.is_dotted() => Some(t.decor_mut()),
+        Item::ArrayOfTables(a) => a.get_mut(0).map(Table::decor_mut),
+        _ => None,
+    };
+    if let Some(decor) = header {
+        let prefix = decor.prefix().and_then(|p| p.as_str()).unwrap_or("");
+        if !prefix.starts_with('\n') {
+            decor.set_prefix(format!("\n{}", prefix));
+        }
+    }
+
+    // The key's decor only fits a key/value pair, and comments can't go
+    // within an inline table.
+    let key_decor = key_decor.filter(|_| slot.is_value() && !inline);
+    tidy_new_inline_entry(doc, tpath);
+    if let (Some(decor), Some((TpathSegment::Name(n), parent))) = (key_decor, tpath.split_last()) {
+        let parent = walk_tpath_mut(doc.as_item_mut(), parent).and_then(Item::as_table_like_mut);
+        if let Some(mut key) = parent.and_then(|t| t.key_mut(n)) {
+            *key.leaf_decor_mut() = decor;
+        }
+    }
+    Ok(())
+}
+
+/// After a key is added at the end of an inline table, drop the space after
+/// the entry before it: that was ahead of the `}`, and would now go before a
+/// comma.
+fn tidy_new_inline_entry(doc: &mut DocumentMut, tpath: &[TpathSegment]) {
+    let Some((TpathSegment::Name(n), parent)) = tpath.split_last() else {
+        return;
+    };
+    if let Some(Item::Value(Value::InlineTable(t))) = walk_tpath_mut(doc.as_item_mut(), parent) {
+        let skip = t.len().saturating_sub(2);
+        let mut entries = t.iter_mut().skip(skip);
+        if let (Some((_, prev)), Some((key, _))) = (entries.next(), entries.next()) {
+            if key.get() == n {
+                prev.decor_mut().set_suffix("");
+            }
+        }
+    }
+}
+
+/// The data as a value: a table becomes an inline table, and an array of
+/// tables an array of inline tables.
+fn inline_item(item: Item) -> Item {
+    match item {
+        Item::Table(t) => Item::Value(Value::InlineTable(t.into_inline_table())),
+        Item::ArrayOfTables(a) => Item::Value(Value::Array(a.into_array())),
+        Item::Value(mut v) => {
+            v.decor_mut().clear();
+            Item::Value(v)
+        }
+        item => item,
+    }
+}
+
+/// The data as a table or array of tables, if it's an inline table or an
+/// array of them.
+fn standard_item(item: Item) -> Item {
+    match item.into_table() {
+        Ok(table) => Item::Table(table),
+        Err(item) => item
+            .into_array_of_tables()
+            .map_or_else(|item| item, Item::ArrayOfTables),
+    }
+}
+
+/// The data, with no positions in the document for the tables within, so
+/// that each is placed after the table before it in the tree.
+fn without_positions(item: Item) -> Item {
+    match item {
+        Item::Table(t) => Item::Table(table_without_positions(&t)),
+        Item::ArrayOfTables(a) => {
+            let mut array = toml_edit::ArrayOfTables::new();
+            for t in a.iter() {
+                array.push(table_without_positions(t));
+            }
+            Item::ArrayOfTables(array)
+        }
+        item => item,
+    }
+}
+
+fn table_without_positions(table: &Table) -> Table {
+    let mut new = Table::new();
+    new.set_implicit(table.is_implicit());
+    new.set_dotted(table.is_dotted());
+    *new.decor_mut() = table.decor().clone();
+    for (k, item) in table.iter() {
+        let key = table.key(k).cloned().unwrap_or_else(|| k.into());
+        new.insert_formatted(&key, without_positions(item.clone()));
+    }
+    new
+}
+
+/// Convert the table or array of tables at the query to the given style,
+/// keeping what comments there's a place for.
+fn convert(path: &Path, query: &str, to: TableStyle) -> Result<(), Error> {
     let tpath = parse_query_cli(query)?.0;
     let mut doc = read_parse(path)?;
+    let cant = |reason| CliError::CantConvert {
+        key: query.into(),
+        reason,
+    };
+    let key_missing = || SilentError::KeyNotFound {
+        key: query.into(),
+        path: Some(path.into()),
+    };
+
+    let (last, parent_tpath) = tpath
+        .split_last()
+        .ok_or_else(|| cant("the whole document is always a table"))?;
+    let TpathSegment::Name(name) = last else {
+        let reason = "an element of an array can only be a table, or only an inline table";
+        Err(cant(reason))?
+    };
+    let parent = walk_tpath_mut(doc.as_item_mut(), parent_tpath).ok_or_else(key_missing)?;
+    // Tables and arrays of tables can't go within an inline table.
+    let in_table = parent.is_table();
+    let table = parent.as_table_like_mut().ok_or_else(key_missing)?;
+    let mut key_decor = table
+        .key(name)
+        .ok_or_else(key_missing)?
+        .leaf_decor()
+        .clone();
+    let item = table
+        .get_mut(name)
+        .filter(|item| !item.is_none())
+        .ok_or_else(key_missing)?;
+
+    *item = match (std::mem::take(item), to) {
+        (Item::Table(t), TableStyle::Inline) => {
+            let (t, comments, trailing) = detach_table_comments(t);
+            key_decor = Decor::new(comments, " ");
+            let mut value = Value::InlineTable(t.into_inline_table());
+            if !trailing.is_empty() {
+                value.decor_mut().set_suffix(format!(" {}", trailing));
+            }
+            Item::Value(value)
+        }
+        (Item::Table(t), TableStyle::Table) if !t.is_dotted() => Item::Table(t),
+        (Item::Table(t), TableStyle::Table) => {
+            let (t, comments, trailing) = detach_table_comments(t);
+            key_decor = Decor::default();
+            Item::Table(attach_table_comments(&t, &comments, &trailing))
+        }
+        (Item::Table(t), TableStyle::Dotted) if t.is_dotted() => Item::Table(t),
+        (Item::Table(t), TableStyle::Dotted) if in_table => {
+            let (mut t, comments, trailing) = detach_table_comments(t);
+            key_decor = Decor::default();
+            t.set_dotted(true);
+            put_dotted_comments(&mut t, &comments, &trailing);
+            Item::Table(t)
+        }
+        (Item::Value(Value::InlineTable(t)), TableStyle::Inline) => {
+            Item::Value(Value::InlineTable(t))
+        }
+        (Item::Value(Value::InlineTable(t)), TableStyle::Table) if in_table => {
+            let (_, comments, _) = split_leading_comment(raw_str(key_decor.prefix()));
+            let trailing = raw_str(t.decor().suffix()).trim().to_owned();
+            let comments = comments.to_owned();
+            key_decor = Decor::default();
+            Item::Table(attach_table_comments(&t.into_table(), &comments, &trailing))
+        }
+        (Item::Value(Value::InlineTable(t)), TableStyle::Dotted) if in_table => {
+            let (_, comments, _) = split_leading_comment(raw_str(key_decor.prefix()));
+            let comments = comments.to_owned();
+            let trailing = raw_str(t.decor().suffix()).trim().to_owned();
+            key_decor = Decor::default();
+            let mut t = t.into_table();
+            t.set_dotted(true);
+            put_dotted_comments(&mut t, &comments, &trailing);
+            Item::Table(t)
+        }
+        (Item::ArrayOfTables(a), TableStyle::Inline) => {
+            let mut array = Array::new();
+            for (i, t) in a.iter().enumerate() {
+                // A blank line between the tables stays between the elements.
+                let (before, _, _) = split_leading_comment(raw_str(t.decor().prefix()));
+                let blank = i > 0 && before.contains('\n');
+                let (t, comments, trailing) = detach_table_comments(t.clone());
+                let mut prefix = String::from(if blank { "\n\n" } else { "\n" });
+                for line in comments.lines().chain([trailing.as_str()]) {
+                    if !line.is_empty() {
+                        prefix += &format!("{}{}\n", INDENT, line.trim());
+                    }
+                }
+                prefix += INDENT;
+                array.push_formatted(
+                    Value::InlineTable(t.into_inline_table()).decorated(prefix, ""),
+                );
+            }
+            array.set_trailing("\n");
+            array.set_trailing_comma(true);
+            key_decor = Decor::default();
+            Item::Value(Value::Array(array))
+        }
+        (Item::ArrayOfTables(a), TableStyle::Table) => Item::ArrayOfTables(a),
+        (Item::Value(Value::Array(a)), TableStyle::Inline) => Item::Value(Value::Array(a)),
+        (Item::Value(Value::Array(a)), TableStyle::Table)
+            if in_table && !a.is_empty() && a.iter().all(Value::is_inline_table) =>
+        {
+            let (_, key_comments, _) = split_leading_comment(raw_str(key_decor.prefix()));
+            let mut comments = key_comments.to_owned();
+            key_decor = Decor::default();
+            let mut array = toml_edit::ArrayOfTables::new();
+            for value in a.iter() {
+                let t = value
+                    .as_inline_table()
+                    .ok_or_else(|| cant("an element of the array isn't an inline table"))?;
+                let prefix = raw_str(value.decor().prefix());
+                for line in prefix.lines().map(str::trim).filter(|l| l.starts_with('#')) {
+                    comments += &format!("{}\n", line);
+                }
+                let trailing = raw_str(value.decor().suffix()).trim().to_owned();
+                array.push(attach_table_comments(
+                    &t.clone().into_table(),
+                    &comments,
+                    &trailing,
+                ));
+                comments.clear();
+            }
+            Item::ArrayOfTables(array)
+        }
+        (Item::ArrayOfTables(_) | Item::Value(Value::Array(_)), TableStyle::Dotted) => {
+            Err(cant("an array of tables can't be written with dotted keys"))?
+        }
+        (Item::Value(Value::Array(_)), _) if in_table => Err(cant(
+            "only a nonempty array of inline tables can be an array of tables",
+        ))?,
+        (Item::Value(Value::InlineTable(_) | Value::Array(_)), _) => {
+            Err(cant("tables can't go within an inline table or array"))?
+        }
+        (item, _) => Err(CliError::WrongType {
+            key: query.into(),
+            expected: "table or array of tables",
+            found: item_type_name(&item),
+        })?,
+    };
+    if let Some(mut key) = table.key_mut(name) {
+        *key.leaf_decor_mut() = key_decor;
+    }
+
+    print!("{}", doc);
+    Ok(())
+}
+
+/// Take the comments from a table: the comment lines before its header,
+/// or for a dotted table, before its first key; and the comment after its
+/// header.
+fn detach_table_comments(mut t: Table) -> (Table, String, String) {
+    let (comments, trailing) = if t.is_dotted() {
+        let first = t.iter_mut().next();
+        let comments = first.map_or(String::new(), |(mut key, _)| {
+            let decor = key.leaf_decor_mut();
+            let prefix = raw_str(decor.prefix()).to_owned();
+            let (before, comments, indent) = split_leading_comment(&prefix);
+            decor.set_prefix(format!("{}{}", before, indent));
+            comments.to_owned()
+        });
+        (comments, String::new())
+    } else {
+        let (_, comments, _) = split_leading_comment(raw_str(t.decor().prefix()));
+        (
+            comments.to_owned(),
+            raw_str(t.decor().suffix()).trim().to_owned(),
+        )
+    };
+    let comments = comments
+        .lines()
+        .map(|l| format!("{}\n", l.trim()))
+        .collect();
+    t.decor_mut().clear();
+    (t, comments, trailing)
+}
+
+/// Make a standard table, with the comments before and after its header.
+fn attach_table_comments(t: &Table, comments: &str, trailing: &str) -> Table {
+    let mut t = table_without_positions(t);
+    t.set_dotted(false);
+    t.set_implicit(false);
+    let suffix = match trailing {
+        "" => String::new(),
+        trailing => format!(" {}", trailing),
+    };
+    *t.decor_mut() = Decor::new(format!("\n{}", comments), suffix);
+    t
+}
+
+/// Put the comment lines before the first key of a dotted table, and the
+/// trailing comment after its last value.  If the table ends in a table
+/// rather than a value, the trailing comment joins the lines before the
+/// first key instead.
+fn put_dotted_comments(t: &mut Table, comments: &str, trailing: &str) {
+    let mut comments = comments.to_owned();
+    if !trailing.is_empty() && !matches!(t.iter().last(), Some((_, Item::Value(_)))) {
+        comments += &format!("{}\n", trailing);
+    }
+    if let Some((mut key, _)) = t.iter_mut().next() {
+        let decor = key.leaf_decor_mut();
+        let prefix = raw_str(decor.prefix()).to_owned();
+        decor.set_prefix(format!("{}{}", comments, prefix.trim_start()));
+    }
+    if let Some((_, Item::Value(value))) = t.iter_mut().last() {
+        if !trailing.is_empty() {
+            value.decor_mut().set_suffix(format!(" {}", trailing));
+        }
+    }
+}
+
+/// Place the value at the tpath, creating tables as needed in the style
+/// given by `opts`.
+///
+/// With a schema, the value is written as the type the schema gives for it,
+/// if it can be; and if the edited document breaks the schema in a way the
+/// original didn't, that's an error.
+fn set_in_doc(
+    doc: &mut DocumentMut,
+    tpath: &[TpathSegment],
+    value_str: &str,
+    opts: &SetOpts,
+    schema: Option<&Schema>,
+) -> Result<(), Error> {
+    let types = schema.map_or(vec![], |schema| schema.types_at(tpath));
+    let before = schema.map(|schema| schema.violations(doc.as_item()));
+
+    let (item, _) = place_in_doc(doc, tpath, opts)?;
+    *item = Item::Value(new_value(item, value_str, opts.string_style, &types)?);
+    tidy_new_inline_entry(doc, tpath);
+
+    if let (Some(schema), Some(before)) = (schema, before) {
+        let new: Vec<_> = schema
+            .violations(doc.as_item())
+            .into_iter()
+            .filter(|v| !before.iter().any(|b| b.same_as(v)))
+            .map(|v| format!("{}: {}", format_tpath(&v.tpath), v.message))
+            .collect();
+        if !new.is_empty() {
+            Err(CliError::SchemaViolation(new.join("; ")))?;
+        }
+    }
+    Ok(())
+}
 
+/// Find the spot at the tpath, creating tables on the way as needed in the
+/// style given by `opts`.  Return it, and whether it's within an inline
+/// table or array, where only values can go.
+///
+/// A new table comes right after the existing tables under the same parent,
+/// rather than at the end of the file: `toml_edit` places a table with no
+/// position of its own after the table before it in the document tree.
+fn place_in_doc<'a>(
+    doc: &'a mut DocumentMut,
+    tpath: &[TpathSegment],
+    opts: &SetOpts,
+) -> Result<(&'a mut Item, bool), Error> {
+    let mut tpath = tpath;
     let mut item = doc.as_item_mut();
     let mut already_inline = false;
-    let mut tpath = &tpath[..];
     use TpathSegment::{Name, Num};
     while let Some(seg) = tpath.first() {
         tpath = &tpath[1..]; // TODO simplify to `for`, unless end up needing a tail
@@ -216,10 +2607,25 @@ fn set(path: &PathBuf, query: &str, value_str: &str) -> Result<(), Error> {
                     Item::Value(Value::InlineTable(_)) => already_inline = true,
                     // TODO make this more directly construct the new, inner part?
                     _ => {
-                        *item = if already_inline {
-                            Item::Value(Value::InlineTable(Default::default()))
-                        } else {
-                            Item::Table(Table::new())
+                        *item = match opts.style {
+                            _ if already_inline => {
+                                Item::Value(Value::InlineTable(Default::default()))
+                            }
+                            TableStyle::Inline => {
+                                already_inline = true;
+                                Item::Value(Value::InlineTable(Default::default()))
+                            }
+                            TableStyle::Table => {
+                                // Print no header unless the table gets values.
+                                let mut table = Table::new();
+                                table.set_implicit(true);
+                                Item::Table(table)
+                            }
+                            TableStyle::Dotted => {
+                                let mut table = Table::new();
+                                table.set_dotted(true);
+                                Item::Table(table)
+                            }
                         }
                     }
                 };
@@ -227,19 +2633,380 @@ fn set(path: &PathBuf, query: &str, value_str: &str) -> Result<(), Error> {
             }
         }
     }
-    *item = value(value_str);
+    Ok((item, already_inline))
+}
+
+/// Whether the item already has the value `set` would give it: the same
+/// type and data, as the schema `types` would have it written.
+fn has_value(item: &Item, value_str: &str, opts: &SetOpts, types: &[&str]) -> bool {
+    if !item.is_value() {
+        return false;
+    }
+    let Ok(new) = new_value(item, value_str, opts.string_style, types) else {
+        return false;
+    };
+    let new = Item::Value(new);
+    item_type_name(item) == item_type_name(&new)
+        && serde_json::to_value(JsonItem(item)).ok() == serde_json::to_value(JsonItem(&new)).ok()
+}
+
+/// The value for `set` to put in place of `old`, keeping the old value's
+/// formatting where possible.
+///
+/// The old value's surrounding whitespace and trailing comment are kept.
+/// In `auto` style, a string keeps the old string's quoting, and an integer
+/// replacing an integer keeps its notation: hex, octal, or binary, and `_`
+/// between groups of digits.  Given the JSON Schema `types` for the value,
+/// in `auto` style it's written as the first of those it can be, and only
+/// as an integer if one is allowed.
+fn new_value(
+    old: &Item,
+    value_str: &str,
+    style: StringStyle,
+    types: &[&str],
+) -> Result<Value, Error> {
+    let typed = match style {
+        StringStyle::Auto => types.iter().find_map(|ty| typed_raw(old, value_str, ty)),
+        _ => None,
+    };
+    let integer_ok = types.is_empty() || types.contains(&"integer") || types.contains(&"number");
+    let raw = match (old, style) {
+        _ if typed.is_some() => typed.unwrap(),
+        (Item::Value(Value::Integer(i)), StringStyle::Auto) if integer_ok => {
+            parse_integer(value_str)
+                .map(|n| format_integer_like(n, &i.display_repr()))
+                .unwrap_or_else(|| encode_basic_string(value_str, false))
+        }
+        (Item::Value(Value::String(s)), StringStyle::Auto) => {
+            let old_raw = s.display_repr();
+            let literal = old_raw.starts_with('\'');
+            let multiline = old_raw.starts_with("\"\"\"") || old_raw.starts_with("'''");
+            match literal.then(|| encode_literal_string(value_str, multiline)) {
+                Some(Some(raw)) => raw,
+                _ => encode_basic_string(value_str, multiline),
+            }
+        }
+        (_, StringStyle::Auto) => Value::from(value_str).to_string(),
+        (_, StringStyle::Basic) => encode_basic_string(value_str, false),
+        (_, StringStyle::Multiline) => encode_basic_string(value_str, true),
+        (_, StringStyle::Literal) => encode_literal_string(value_str, false)
+            .ok_or_else(|| CliError::NotLiteral(value_str.into()))?,
+    };
+    let mut new: Value = raw.parse()?;
+    match old.as_value() {
+        Some(old) => *new.decor_mut() = old.decor().clone(),
+        None => new.decor_mut().clear(),
+    }
+    Ok(new)
+}
+
+/// The value written as the given JSON Schema type, if it can be, unless
+/// that's a string.
+fn typed_raw(old: &Item, value_str: &str, ty: &str) -> Option<String> {
+    match ty {
+        "integer" => parse_integer(value_str).map(|n| match old {
+            Item::Value(Value::Integer(i)) => format_integer_like(n, &i.display_repr()),
+            _ => n.to_string(),
+        }),
+        "number" => match value_str.parse::<Value>() {
+            Ok(Value::Integer(_) | Value::Float(_)) => Some(value_str.trim().to_string()),
+            _ => None,
+        },
+        "boolean" => matches!(value_str, "true" | "false").then(|| value_str.to_string()),
+        _ => None,
+    }
+}
+
+/// Parse an integer in any of TOML's notations, like `1_000` or `0xff`.
+fn parse_integer(s: &str) -> Option<i64> {
+    match s.parse::<Value>() {
+        Ok(Value::Integer(i)) => Some(*i.value()),
+        _ => None,
+    }
+}
+
+/// Write an integer in the same notation as the TOML integer `old`.
+fn format_integer_like(n: i64, old: &str) -> String {
+    let unsigned = old.trim_start_matches(['+', '-']);
+    let (prefix, old_digits) = match unsigned.get(..2) {
+        Some(prefix @ ("0x" | "0o" | "0b")) if n >= 0 => (prefix, &unsigned[2..]),
+        _ => ("", unsigned),
+    };
+    let width = old_digits.chars().filter(|&c| c != '_').count();
+    let mut digits = match prefix {
+        "0x" if old_digits.chars().any(|c| c.is_ascii_uppercase()) => format!("{:0width$X}", n),
+        "0x" => format!("{:0width$x}", n),
+        "0o" => format!("{:0width$o}", n),
+        "0b" => format!("{:0width$b}", n),
+        _ => n.unsigned_abs().to_string(),
+    };
+    if let Some((_, last_group)) = old_digits.rsplit_once('_') {
+        digits = group_digits(&digits, last_group.len().max(1));
+    }
+    let sign = match (n < 0, old.starts_with('+') && prefix.is_empty()) {
+        (true, _) => "-",
+        (false, true) => "+",
+        (false, false) => "",
+    };
+    format!("{}{}{}", sign, prefix, digits)
+}
+
+/// Put `_` between each group of this many digits, counting from the right.
+fn group_digits(digits: &str, group: usize) -> String {
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(group) {
+            out.push('_');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Write a string in TOML's basic or multi-line basic syntax.
+fn encode_basic_string(s: &str, multiline: bool) -> String {
+    let mut out = String::from(if multiline { "\"\"\"\n" } else { "\"" });
+    // In a multi-line string, only a run of three quotes needs escaping.
+    let mut quotes = 0;
+    for c in s.chars() {
+        match c {
+            '"' if multiline && quotes < 2 => {
+                quotes += 1;
+                out.push(c);
+                continue;
+            }
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' | '\t' if multiline => out.push(c),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+        quotes = 0;
+    }
+    if quotes > 0 {
+        // Keep the string's last quotes apart from the closing delimiter.
+        out.truncate(out.len() - quotes);
+        out.push_str(&"\\\"".repeat(quotes));
+    }
+    out.push_str(if multiline { "\"\"\"" } else { "\"" });
+    out
+}
+
+/// Write a string in TOML's literal or multi-line literal syntax, if it
+/// can be: a literal string has no escapes.
+fn encode_literal_string(s: &str, multiline: bool) -> Option<String> {
+    let plain = s
+        .chars()
+        .all(|c| c == '\t' || (multiline && c == '\n') || !c.is_control());
+    if !plain || (multiline && (s.contains("'''") || s.ends_with('\''))) {
+        None
+    } else if multiline {
+        Some(format!("'''\n{}'''", s))
+    } else if s.contains('\'') {
+        None
+    } else {
+        Some(format!("'{}'", s))
+    }
+}
+
+fn comment_get(path: &Path, query: &str, opts: &CommentOpts) -> Result<(), Error> {
+    let tpath = parse_query_cli(query)?.0;
+    let mut doc = read_parse(path)?;
+    let text = with_comment_decor(&mut doc, query, &tpath, opts.trailing, |decor| {
+        if opts.trailing {
+            let suffix = decor.suffix().and_then(|s| s.as_str()).unwrap_or("");
+            trailing_comment_text(suffix).map(String::from)
+        } else {
+            let prefix = decor.prefix().and_then(|s| s.as_str()).unwrap_or("");
+            let (_, comment, _) = split_leading_comment(prefix);
+            let lines: Vec<_> = comment.lines().filter_map(trailing_comment_text).collect();
+            Some(lines.join("\n")).filter(|_| !comment.is_empty())
+        }
+    })?;
+    if let Some(text) = text {
+        println!("{}", text);
+    }
+    Ok(())
+}
+
+fn comment_set(path: &Path, query: &str, text: &str, opts: &CommentOpts) -> Result<(), Error> {
+    let tpath = parse_query_cli(query)?.0;
+    let mut doc = read_parse(path)?;
+    if opts.trailing && text.contains('\n') {
+        Err(CliError::MultilineTrailingComment())?;
+    }
+    with_comment_decor(&mut doc, query, &tpath, opts.trailing, |decor| {
+        if opts.trailing {
+            let suffix = decor.suffix().and_then(|s| s.as_str()).unwrap_or("");
+            let space = &suffix[..suffix.len() - suffix.trim_start().len()];
+            let space = if space.is_empty() { " " } else { space };
+            match text {
+                "" => decor.set_suffix(""),
+                _ => decor.set_suffix(format!("{}{}", space, comment_line(text))),
+            }
+        } else {
+            let prefix = decor.prefix().and_then(|s| s.as_str()).unwrap_or("");
+            let (before, _, indent) = split_leading_comment(prefix);
+            let mut new_prefix = before.to_string();
+            for line in text.lines() {
+                new_prefix += &format!("{}{}\n", indent, comment_line(line));
+            }
+            new_prefix += indent;
+            decor.set_prefix(new_prefix);
+        }
+    })?;
 
-    // TODO actually write back
     print!("{}", doc);
     Ok(())
 }
 
+/// Act on the decor that holds the comment on the data at this tpath.
+///
+/// For a key with a plain value, the leading comment is in the key's
+/// decor and the trailing comment in the value's.  Tables in an array of
+/// tables, and elements of an array, carry their own.
+fn with_comment_decor<T>(
+    doc: &mut DocumentMut,
+    query: &str,
+    tpath: &[TpathSegment],
+    trailing: bool,
+    f: impl FnOnce(&mut Decor) -> T,
+) -> Result<T, Error> {
+    use TpathSegment::{Name, Num};
+    let no_place = |reason| CliError::NoCommentPlace {
+        key: query.into(),
+        reason,
+    };
+    let key_missing = || SilentError::KeyNotFound {
+        key: query.into(),
+        path: None,
+    };
+
+    let (last, parent_tpath) = tpath
+        .split_last()
+        .ok_or_else(|| no_place("the whole document has no line of its own"))?;
+    // Comments can't go anywhere within an inline table.
+    for i in 0..tpath.len() {
+        if let Some(Item::Value(Value::InlineTable(_))) = walk_tpath(doc.as_item(), &tpath[..i]) {
+            Err(no_place("comments can't go inside an inline table"))?;
+        }
+    }
+    let parent = walk_tpath_mut(doc.as_item_mut(), parent_tpath).ok_or_else(key_missing)?;
+    match last {
+        Name(n) => {
+            let table = parent.as_table_like_mut().ok_or_else(key_missing)?;
+            match table.get_mut(n).ok_or_else(key_missing)? {
+                Item::Table(t) if t.is_dotted() || t.is_implicit() => Err(no_place(
+                    "the table has no header line; try one of its keys",
+                ))?,
+                Item::Table(t) => Ok(f(t.decor_mut())),
+                Item::ArrayOfTables(_) => {
+                    Err(no_place("try one table of the array, like `foo[0]`"))?
+                }
+                Item::Value(v) if trailing => Ok(f(v.decor_mut())),
+                _ => {
+                    let mut key = table.key_mut(n).ok_or_else(key_missing)?;
+                    Ok(f(key.leaf_decor_mut()))
+                }
+            }
+        }
+        Num(n) => match parent {
+            Item::ArrayOfTables(a) => Ok(f(a.get_mut(*n).ok_or_else(key_missing)?.decor_mut())),
+            Item::Value(Value::Array(_)) if trailing => {
+                Err(no_place("an array element can only have a leading comment"))?
+            }
+            Item::Value(Value::Array(a)) => {
+                Ok(f(a.get_mut(*n).ok_or_else(key_missing)?.decor_mut()))
+            }
+            _ => Err(key_missing())?,
+        },
+    }
+}
+
+/// Split a decor prefix into the text before the item's leading comment,
+/// the comment itself (the comment lines just before the item), and the
+/// indentation of the item's own line.
+fn split_leading_comment(prefix: &str) -> (&str, &str, &str) {
+    let indent_start = prefix.rfind('\n').map_or(0, |i| i + 1);
+    let mut comment_start = indent_start;
+    while comment_start > 0 {
+        let line_start = prefix[..comment_start - 1].rfind('\n').map_or(0, |i| i + 1);
+        if !prefix[line_start..comment_start]
+            .trim_start()
+            .starts_with('#')
+        {
+            break;
+        }
+        comment_start = line_start;
+    }
+    (
+        &prefix[..comment_start],
+        &prefix[comment_start..indent_start],
+        &prefix[indent_start..],
+    )
+}
+
+/// The text of a comment, as in `  # text`, if there is one.
+fn trailing_comment_text(s: &str) -> Option<&str> {
+    let text = s.trim_start().strip_prefix('#')?;
+    Some(text.strip_prefix(' ').unwrap_or(text).trim_end())
+}
+
+fn comment_line(text: &str) -> String {
+    if text.is_empty() {
+        "#".into()
+    } else {
+        format!("# {}", text)
+    }
+}
+
 fn parse_query_cli(query: &str) -> Result<Query, CliError> {
-    parse_query(query).map_err(|_err| {
-        CliError::QuerySyntaxError(query.into()) // TODO: perhaps use parse-error details?
+    parse_query(query).map_err(|err| CliError::QuerySyntaxError {
+        query: query.into(),
+        err,
     })
 }
 
+/// Parse a query for a spot to put data in, which can't be the whole
+/// document: that's always a table.
+fn parse_dest_query_cli(query: &str) -> Result<Vec<TpathSegment>, CliError> {
+    let tpath = parse_query_cli(query)?.0;
+    if tpath.is_empty() {
+        return Err(CliError::RootDestination(query.into()));
+    }
+    Ok(tpath)
+}
+
+/// Point at the spot in the query where parsing failed, and say what
+/// was expected there.
+fn describe_query_error(query: &str, err: &QueryError) -> String {
+    let column = query[..err.offset].chars().count();
+    let mut out = format!(
+        "  {}\n  {}^ expected {}",
+        query,
+        " ".repeat(column),
+        err.expected
+    );
+    if let Some(hint) = &err.hint {
+        out += &format!("\nhint: {}", hint);
+    }
+    out
+}
+
+fn walk_tpath_mut<'a>(mut item: &'a mut Item, tpath: &[TpathSegment]) -> Option<&'a mut Item> {
+    use TpathSegment::{Name, Num};
+    for seg in tpath {
+        match seg {
+            Name(n) => item = item.get_mut(n)?,
+            Num(n) => item = item.get_mut(n)?,
+        }
+    }
+    Some(item)
+}
+
 fn walk_tpath<'a>(
     mut item: &'a toml_edit::Item,
     tpath: &[TpathSegment],
@@ -299,7 +3066,6 @@ impl Serialize for JsonValue<'_> {
     where
         S: Serializer,
     {
-        #[allow(clippy::redundant_pattern_matching)]
         if let Some(v) = self.0.as_integer() {
             v.serialize(serializer)
         } else if let Some(v) = self.0.as_float() {
@@ -308,8 +3074,9 @@ impl Serialize for JsonValue<'_> {
             v.serialize(serializer)
         } else if let Some(v) = self.0.as_str() {
             v.serialize(serializer)
-        } else if let Some(_) = self.0.as_datetime() {
-            "UNIMPLEMENTED: DateTime".serialize(serializer) // TODO
+        } else if let Some(v) = self.0.as_datetime() {
+            // In RFC 3339 form, as JSON Schema's `date-time` format expects.
+            v.to_string().serialize(serializer)
         } else if let Some(arr) = self.0.as_array() {
             let mut seq = serializer.serialize_seq(Some(arr.len()))?;
             for e in arr.iter() {
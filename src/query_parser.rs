@@ -3,7 +3,7 @@ use std::convert::TryFrom;
 /// Query language is simple: a query is a "TOML path", or tpath.
 pub struct Query(pub Vec<TpathSegment>);
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum TpathSegment {
     Name(String),
     Num(usize),
@@ -13,10 +13,9 @@ use nom::{
     branch::alt,
     bytes::complete::{escaped_transform, tag, take_while1, take_while_m_n},
     character::complete::{char, digit1, none_of, one_of},
-    combinator::{all_consuming, map, map_res},
+    combinator::{map, map_res},
     error::Error,
-    multi::many0,
-    sequence::{delimited, preceded, tuple},
+    sequence::{delimited, preceded},
     Err, IResult,
 };
 
@@ -72,29 +71,162 @@ fn tpath_segment_num(s: &str) -> IResult<&str, TpathSegment> {
     map(delimited(char('['), array_index, char(']')), TpathSegment::Num)(s)
 }
 
-#[rustfmt::skip]
-fn tpath_segment_rest(s: &str) -> IResult<&str, TpathSegment> {
-    alt((
-        preceded(char('.'), tpath_segment_name),
-        tpath_segment_num,
-    ))(s)
+/// A syntax error in a query, and where it was found.
+#[derive(Debug, PartialEq, Eq)]
+pub struct QueryError {
+    /// Byte offset into the query where parsing failed.
+    pub offset: usize,
+    /// A description of what was expected at that point.
+    pub expected: &'static str,
+    /// A suggestion for fixing the query, if we have one.
+    pub hint: Option<String>,
 }
 
-#[rustfmt::skip]
-fn tpath(s: &str) -> IResult<&str, Vec<TpathSegment>> {
-    alt((
-        map(all_consuming(char('.')), |_| vec![]),
-        // Must start with a name, because TOML root is always a table.
-        map(tuple((tpath_segment_name, many0(tpath_segment_rest))),
-            |(hd, mut tl)| { tl.insert(0, hd); tl }),
-    ))(s)
+fn offset_of(s: &str, err: Err<Error<&str>>) -> usize {
+    match err {
+        Err::Error(e) | Err::Failure(e) => s.len() - e.input.len(),
+        Err::Incomplete(_) => s.len(),
+    }
+}
+
+fn tpath_segment_name_at(s: &str, offset: usize) -> Result<(usize, TpathSegment), QueryError> {
+    let rest = &s[offset..];
+    // Try a quoted key alone when there's a quote, so that the error points
+    // into the string rather than back at the quote.
+    let result = if rest.starts_with('"') {
+        map(basic_string, TpathSegment::Name)(rest)
+    } else {
+        tpath_segment_name(rest)
+    };
+    match result {
+        Ok((remaining, seg)) => Ok((s.len() - remaining.len(), seg)),
+        Err(err) if rest.starts_with('"') => Err(QueryError {
+            offset: offset + offset_of(rest, err),
+            expected: "a closing `\"` (or a valid escape sequence)",
+            hint: None,
+        }),
+        Err(_) => Err(QueryError {
+            offset,
+            expected: "a key",
+            hint: if offset == 0 && rest.starts_with('.') {
+                Some("queries start with a key, like `foo.bar` rather than `.foo.bar`".into())
+            } else {
+                quoting_hint(s, offset)
+            },
+        }),
+    }
+}
+
+/// If the query has a character at `offset` that can't appear in a bare key,
+/// suggest quoting the key it's in.
+fn quoting_hint(s: &str, offset: usize) -> Option<String> {
+    let c = s[offset..].chars().next()?;
+    if c.is_ascii_alphanumeric() || "-_.[]\"".contains(c) {
+        return None;
+    }
+    let start = s[..offset].rfind(['.', ']']).map_or(0, |i| i + 1);
+    let end = s[offset..].find(['.', '[']).map_or(s.len(), |i| offset + i);
+    let key = &s[start..end];
+    let quoted = key.replace('\\', "\\\\").replace('"', "\\\"");
+    Some(format!(
+        "keys with characters like {:?} must be quoted; try `{}\"{}\"{}`",
+        c,
+        &s[..start],
+        quoted,
+        &s[end..]
+    ))
+}
+
+pub fn parse_query(s: &str) -> Result<Query, QueryError> {
+    if s == "." {
+        return Ok(Query(vec![]));
+    }
+
+    // Must start with a name, because TOML root is always a table.
+    let (mut offset, seg) = tpath_segment_name_at(s, 0)?;
+    let mut tpath = vec![seg];
+    while offset < s.len() {
+        let rest = &s[offset..];
+        if rest.starts_with('.') {
+            let (next, seg) = tpath_segment_name_at(s, offset + 1)?;
+            tpath.push(seg);
+            offset = next;
+        } else if rest.starts_with('[') {
+            let (remaining, seg) = tpath_segment_num(rest).map_err(|err| {
+                let at = offset + offset_of(rest, err);
+                if at == offset + 1 {
+                    QueryError {
+                        offset: at,
+                        expected: "an array index",
+                        hint: Some(
+                            "array indexes are numbers, like `foo[0]`; \
+                                    use `.` to index into a table"
+                                .into(),
+                        ),
+                    }
+                } else {
+                    QueryError {
+                        offset: at,
+                        expected: "`]`",
+                        hint: None,
+                    }
+                }
+            })?;
+            tpath.push(seg);
+            offset = s.len() - remaining.len();
+        } else {
+            return Err(QueryError {
+                offset,
+                expected: "`.` or `[`",
+                hint: quoting_hint(s, offset),
+            });
+        }
+    }
+    Ok(Query(tpath))
 }
 
-pub fn parse_query(s: &str) -> Result<Query, Err<Error<&str>>> {
-    all_consuming(tpath)(s).map(|(trailing, res)| {
-        assert!(trailing.is_empty());
-        Query(res)
-    })
+/// Format a tpath as a query, such that `parse_query` would read it back.
+///
+/// Keys are written bare where possible, and otherwise quoted.
+pub fn format_tpath(tpath: &[TpathSegment]) -> String {
+    if tpath.is_empty() {
+        return ".".into();
+    }
+    let mut out = String::new();
+    for (i, seg) in tpath.iter().enumerate() {
+        match seg {
+            TpathSegment::Name(n) => {
+                if i > 0 {
+                    out.push('.');
+                }
+                format_key(&mut out, n);
+            }
+            TpathSegment::Num(n) => out.push_str(&format!("[{}]", n)),
+        }
+    }
+    out
+}
+
+fn format_key(out: &mut String, key: &str) {
+    if matches!(bare_string(key), Ok(("", _))) {
+        out.push_str(key);
+        return;
+    }
+    out.push('"');
+    for c in key.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\x08' => out.push_str("\\b"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\x0c' => out.push_str("\\f"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
 }
 
 #[test]
@@ -123,3 +255,41 @@ fn test_parse_query() {
         }
     }
 }
+
+#[test]
+fn test_parse_query_error() {
+    for (s, offset, expected) in [
+        ("", 0, "a key"),
+        (".a", 0, "a key"),
+        ("a..b", 2, "a key"),
+        ("a.", 2, "a key"),
+        ("a b", 1, "`.` or `[`"),
+        ("a[b]", 2, "an array index"),
+        ("a[1", 3, "`]`"),
+        ("\"a.b", 4, "a closing `\"` (or a valid escape sequence)"),
+    ] {
+        let err = parse_query(s).err().unwrap();
+        assert_eq!((offset, expected), (err.offset, err.expected), "{}", s);
+    }
+
+    let hint = |s| parse_query(s).err().unwrap().hint.unwrap();
+    assert!(hint("foo bar.baz").ends_with("try `\"foo bar\".baz`"));
+    assert!(hint("a.b c[0]").ends_with("try `a.\"b c\"[0]`"));
+}
+
+#[test]
+fn test_format_tpath() {
+    use TpathSegment::{Name, Num};
+    let name = |n: &str| Name(n.to_string());
+    for (tpath, expected) in [
+        (vec![], "."),
+        (vec![name("a"), Num(1), name("b-c_D")], "a[1].b-c_D"),
+        (vec![name("a.b")], "\"a.b\""),
+        (vec![name("")], "\"\""),
+        (vec![name("q\"\\ \t\u{7f}‽")], "\"q\\\"\\\\ \\t\\u007F‽\""),
+    ] {
+        let actual = format_tpath(&tpath);
+        assert_eq!(expected, actual);
+        assert_eq!(tpath, parse_query(&actual).unwrap().0);
+    }
+}
@@ -0,0 +1,171 @@
+//! Checking TOML data against a JSON Schema, for `toml validate`.
+//!
+//! The data is checked in its JSON form, the same as `toml get` prints.
+
+use std::fs;
+use std::path::{Component, Path, Prefix};
+
+use anyhow::Error;
+use jsonschema::error::ValidationErrorKind;
+use jsonschema::paths::LocationSegment;
+use toml_edit::Item;
+
+use crate::query_parser::TpathSegment;
+use crate::{CliError, IoError, JsonItem};
+
+pub struct Schema {
+    json: serde_json::Value,
+    validator: jsonschema::Validator,
+}
+
+/// A spot where the data doesn't match the schema.
+pub struct Violation {
+    pub tpath: Vec<TpathSegment>,
+    /// The schema keyword that failed, like `type` or `required`.
+    pub keyword: String,
+    pub message: String,
+}
+
+impl Violation {
+    /// Whether this is the same problem as `other`, in the same spot, even
+    /// if the details differ.
+    pub fn same_as(&self, other: &Violation) -> bool {
+        self.tpath == other.tpath && self.keyword == other.keyword
+    }
+}
+
+impl Schema {
+    /// Read a schema from a JSON file.  A `$ref` to another file is
+    /// resolved relative to this one, if its path can be written as a
+    /// `file:` URI.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let bad_schema = |message: String| CliError::BadSchema {
+            path: path.into(),
+            message,
+        };
+        let data = fs::read_to_string(path).map_err(IoError::wrap(path))?;
+        let json: serde_json::Value =
+            serde_json::from_str(&data).map_err(|err| bad_schema(err.to_string()))?;
+        let base = fs::canonicalize(path).map_err(IoError::wrap(path))?;
+        let mut options = jsonschema::options();
+        if let Some(uri) = file_uri(&base) {
+            options = options.with_base_uri(uri);
+        }
+        let validator = options
+            .build(&json)
+            .map_err(|err| bad_schema(err.to_string()))?;
+        Ok(Schema { json, validator })
+    }
+
+    /// The types the schema gives for the data at the tpath, like `integer`,
+    /// as far as `properties`, `additionalProperties`, `items`, and `$ref`s
+    /// within the schema tell.
+    pub fn types_at(&self, tpath: &[TpathSegment]) -> Vec<&str> {
+        let mut node = self.resolve(&self.json);
+        for seg in tpath {
+            let next = match seg {
+                TpathSegment::Name(n) => node
+                    .pointer(&format!("/properties/{}", escape_pointer(n)))
+                    .or_else(|| node.get("additionalProperties")),
+                TpathSegment::Num(_) => node.get("items"),
+            };
+            match next.filter(|next| next.is_object()) {
+                Some(next) => node = self.resolve(next),
+                None => return vec![],
+            }
+        }
+        match node.get("type") {
+            Some(serde_json::Value::String(ty)) => vec![ty],
+            Some(serde_json::Value::Array(types)) => {
+                types.iter().filter_map(|ty| ty.as_str()).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Follow `$ref`s to elsewhere in the schema.
+    fn resolve<'a>(&'a self, mut node: &'a serde_json::Value) -> &'a serde_json::Value {
+        // Give up on a cycle of references.
+        for _ in 0..32 {
+            let target = node
+                .get("$ref")
+                .and_then(|r| r.as_str()?.strip_prefix('#'))
+                .and_then(|pointer| self.json.pointer(pointer));
+            match target {
+                Some(target) => node = target,
+                None => break,
+            }
+        }
+        node
+    }
+
+    /// Each spot where the data doesn't match the schema.
+    pub fn violations(&self, item: &Item) -> Vec<Violation> {
+        let instance = serde_json::to_value(JsonItem(item)).unwrap();
+        self.validator
+            .iter_errors(&instance)
+            .flat_map(|err| {
+                let tpath: Vec<_> = err
+                    .instance_path()
+                    .iter()
+                    .map(|seg| match seg {
+                        LocationSegment::Property(name) => TpathSegment::Name(name.into_owned()),
+                        LocationSegment::Index(i) => TpathSegment::Num(i),
+                    })
+                    .collect();
+                let keyword = err.kind().keyword().to_owned();
+                match err.kind() {
+                    // Point at each unexpected key, rather than the whole table.
+                    ValidationErrorKind::AdditionalProperties { unexpected } => unexpected
+                        .iter()
+                        .map(|name| Violation {
+                            tpath: [&tpath[..], &[TpathSegment::Name(name.clone())]].concat(),
+                            keyword: keyword.clone(),
+                            message: format!(
+                                "Additional properties are not allowed ('{}' was unexpected)",
+                                name
+                            ),
+                        })
+                        .collect(),
+                    _ => vec![Violation {
+                        tpath,
+                        keyword,
+                        message: err.to_string(),
+                    }],
+                }
+            })
+            .collect()
+    }
+}
+
+/// The `file:` URI for an absolute path, if it needs no percent-encoding:
+/// the resolver takes the path back out of the URI without decoding it.
+fn file_uri(path: &Path) -> Option<String> {
+    let mut uri = String::from("file://");
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => match prefix.kind() {
+                Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => {
+                    uri += &format!("/{}:", letter as char);
+                }
+                _ => return None,
+            },
+            Component::RootDir => {}
+            Component::Normal(name) => {
+                let name = name.to_str()?;
+                let plain = |c: char| c.is_ascii_alphanumeric() || "-._~!$&'()*+,;=:@".contains(c);
+                if !name.chars().all(plain) {
+                    return None;
+                }
+                uri.push('/');
+                uri += name;
+            }
+            Component::CurDir | Component::ParentDir => return None,
+        }
+    }
+    Some(uri)
+}
+
+fn escape_pointer(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
@@ -0,0 +1,327 @@
+//! Sorting tables and arrays, for `toml sort`.
+//!
+//! Each key carries its comments with it, and so does each array element.
+//! Whitespace that only separates elements, like the space after `[` or
+//! before `]`, stays in place.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use toml_edit::{
+    Array, ArrayOfTables, Decor, InlineTable, Item, RawString, Table, TableLike, Value,
+};
+
+use crate::query_parser::TpathSegment;
+use crate::walk_tpath;
+
+/// Sort the keys of a table.
+///
+/// Key/value pairs are sorted among themselves, and so are the tables
+/// within, by moving their headers.  With `recursive`, the same goes for
+/// every table within.
+pub fn sort_table(table: &mut Table, recursive: bool) {
+    // Comments set off by a blank line before the first key, like one at
+    // the top of the file, aren't about that key, so they stay on top.
+    let top = table
+        .iter_mut()
+        .find(|(_, item)| item.is_value())
+        .and_then(|(mut key, _)| {
+            let decor = key.leaf_decor_mut();
+            let prefix = decor.prefix().and_then(RawString::as_str)?.to_owned();
+            let (top, rest) = prefix.split_at(prefix.rfind("\n\n")? + 2);
+            decor.set_prefix(rest);
+            Some(top.to_owned())
+        });
+    table.sort_values();
+    if let Some((top, (mut key, _))) = top.zip(table.iter_mut().find(|(_, item)| item.is_value())) {
+        let decor = key.leaf_decor_mut();
+        let prefix = decor.prefix().and_then(RawString::as_str).unwrap_or("");
+        decor.set_prefix(format!("{}{}", top, prefix));
+    }
+
+    // The headers of sub-tables appear in the order of their positions in
+    // the document.  So hand out the positions those tables already hold,
+    // in the new order.
+    let groups: Vec<Vec<Slot>> = table
+        .iter()
+        .map(|(_, item)| {
+            let mut slots = vec![];
+            header_slots(item, &mut slots);
+            slots.sort_unstable();
+            slots
+        })
+        .collect();
+    reassign_slots(table.iter_mut().map(|(_, item)| item), &groups);
+
+    if recursive {
+        for (_, item) in table.iter_mut() {
+            sort_tables_within(item);
+        }
+    }
+}
+
+pub fn sort_inline_table(table: &mut InlineTable, recursive: bool) {
+    // The whitespace before each key is just separation, so it stays put.
+    let prefixes: Vec<_> = table
+        .iter()
+        .map(|(key, _)| {
+            table
+                .key(key)
+                .and_then(|k| k.leaf_decor().prefix())
+                .cloned()
+        })
+        .collect();
+    table.sort_values();
+    for ((mut key, value), prefix) in table.iter_mut().zip(prefixes) {
+        if let Some(prefix) = prefix {
+            key.leaf_decor_mut().set_prefix(prefix);
+        }
+        if recursive {
+            sort_tables_within_value(value);
+        }
+    }
+}
+
+fn sort_tables_within(item: &mut Item) {
+    match item {
+        Item::Table(table) => sort_table(table, true),
+        Item::ArrayOfTables(array) => {
+            for table in array.iter_mut() {
+                sort_table(table, true);
+            }
+        }
+        Item::Value(value) => sort_tables_within_value(value),
+        Item::None => {}
+    }
+}
+
+fn sort_tables_within_value(value: &mut Value) {
+    match value {
+        Value::InlineTable(table) => sort_inline_table(table, true),
+        Value::Array(array) => {
+            for value in array.iter_mut() {
+                sort_tables_within_value(value);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Sort an array of scalars, by value.
+///
+/// If some element isn't a scalar, return its type and leave the array alone.
+pub fn sort_array(array: &mut Array) -> Result<(), &'static str> {
+    if let Some(value) = array
+        .iter()
+        .find(|value| matches!(value, Value::Array(_) | Value::InlineTable(_)))
+    {
+        return Err(value.type_name());
+    }
+    let mut order: Vec<usize> = (0..array.len()).collect();
+    order.sort_by(|&i, &j| compare_values(array.get(i), array.get(j)));
+    reorder_array(array, &order);
+    Ok(())
+}
+
+/// Sort an array of inline tables by the value at `by` within each, with
+/// those lacking it at the end.
+///
+/// If some element isn't a table, return its type and leave the array alone.
+pub fn sort_array_by(array: &mut Array, by: &[TpathSegment]) -> Result<(), &'static str> {
+    if let Some(value) = array.iter().find(|value| !value.is_inline_table()) {
+        return Err(value.type_name());
+    }
+    let field = |i| {
+        let table: &dyn TableLike = array.get(i)?.as_inline_table()?;
+        field_value(table, by)
+    };
+    let mut order: Vec<usize> = (0..array.len()).collect();
+    order.sort_by(|&i, &j| compare_values(field(i), field(j)));
+    reorder_array(array, &order);
+    Ok(())
+}
+
+/// Sort an array of tables by the value at `by` within each, with those
+/// lacking it at the end.
+pub fn sort_array_of_tables_by(array: &mut ArrayOfTables, by: &[TpathSegment]) {
+    let mut tables: Vec<Table> = array.iter().cloned().collect();
+    let groups: Vec<Vec<Slot>> = tables
+        .iter()
+        .map(|table| {
+            let mut slots = vec![];
+            header_slots_within(table, &mut slots);
+            slots.sort_unstable();
+            slots
+        })
+        .collect();
+
+    let mut order: Vec<usize> = (0..tables.len()).collect();
+    order
+        .sort_by(|&i, &j| compare_values(field_value(&tables[i], by), field_value(&tables[j], by)));
+    let groups: Vec<_> = order.iter().map(|&i| groups[i].clone()).collect();
+    let mut sorted: Vec<Item> = order
+        .iter()
+        .map(|&i| Item::Table(std::mem::take(&mut tables[i])))
+        .collect();
+    reassign_slots(sorted.iter_mut(), &groups);
+
+    array.clear();
+    for item in sorted {
+        if let Item::Table(table) = item {
+            array.push(table);
+        }
+    }
+}
+
+fn field_value<'a>(table: &'a dyn TableLike, by: &[TpathSegment]) -> Option<&'a Value> {
+    let item = match by.split_first()? {
+        (TpathSegment::Name(n), rest) => walk_tpath(table.get(n)?, rest)?,
+        (TpathSegment::Num(_), _) => return None,
+    };
+    item.as_value()
+}
+
+/// Order values for sorting: numbers by value, then strings, then other
+/// scalars, and anything missing last.
+fn compare_values(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    fn rank(value: Option<&Value>) -> u8 {
+        match value {
+            Some(Value::Integer(_) | Value::Float(_)) => 0,
+            Some(Value::String(_)) => 1,
+            Some(Value::Boolean(_)) => 2,
+            Some(Value::Datetime(_)) => 3,
+            Some(Value::Array(_) | Value::InlineTable(_)) => 4,
+            None => 5,
+        }
+    }
+    fn number(value: &Value) -> f64 {
+        match value {
+            Value::Integer(i) => *i.value() as f64,
+            Value::Float(f) => *f.value(),
+            _ => 0.,
+        }
+    }
+    match (a, b) {
+        (Some(Value::Integer(a)), Some(Value::Integer(b))) => a.value().cmp(b.value()),
+        (Some(a @ (Value::Integer(_) | Value::Float(_))), Some(b)) if rank(Some(b)) == 0 => {
+            number(a).total_cmp(&number(b))
+        }
+        (Some(Value::String(a)), Some(Value::String(b))) => a.value().cmp(b.value()),
+        (Some(Value::Boolean(a)), Some(Value::Boolean(b))) => a.value().cmp(b.value()),
+        (Some(Value::Datetime(a)), Some(Value::Datetime(b))) => {
+            a.value().to_string().cmp(&b.value().to_string())
+        }
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Put the array's elements in the given order of their old indexes.
+fn reorder_array(array: &mut Array, order: &[usize]) {
+    let values: Vec<Value> = array.iter().cloned().collect();
+    array.clear();
+    for (slot, &i) in order.iter().enumerate() {
+        let mut value = values[i].clone();
+        let (own, there) = (values[i].decor(), values[slot].decor());
+        let prefix = moved_decor(own.prefix(), there.prefix());
+        let suffix = moved_decor(own.suffix(), there.suffix());
+        *value.decor_mut() = Decor::new(prefix, suffix);
+        array.push_formatted(value);
+    }
+}
+
+/// The decor for an element moving into a new spot: its own, if that has a
+/// comment, and otherwise the whitespace that was in the spot.
+fn moved_decor(own: Option<&RawString>, there: Option<&RawString>) -> String {
+    let own = own.and_then(RawString::as_str).unwrap_or("");
+    let there = there.and_then(RawString::as_str).unwrap_or("");
+    if own.contains('#') {
+        return own.to_string();
+    }
+    match there.rsplit_once('\n') {
+        Some((_, indent)) if there.contains('#') => format!("\n{}", indent),
+        _ => there.to_string(),
+    }
+}
+
+/// Where a table header is: its position in the document, and the
+/// whitespace before it, like the blank line that every header but the
+/// first usually has.
+type Slot = (usize, String);
+
+/// The slots of the table headers in this item, and within it.
+fn header_slots(item: &Item, out: &mut Vec<Slot>) {
+    match item {
+        Item::Table(table) => header_slots_within(table, out),
+        Item::ArrayOfTables(array) => {
+            for table in array.iter() {
+                header_slots_within(table, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn header_slots_within(table: &Table, out: &mut Vec<Slot>) {
+    if let Some(position) = table.position().filter(|_| has_header(table)) {
+        let prefix = table
+            .decor()
+            .prefix()
+            .and_then(RawString::as_str)
+            .unwrap_or("");
+        let space = &prefix[..prefix.len() - prefix.trim_start().len()];
+        out.push((position, space.to_owned()));
+    }
+    for (_, item) in table.iter() {
+        header_slots(item, out);
+    }
+}
+
+fn has_header(table: &Table) -> bool {
+    !table.is_implicit() && !table.is_dotted()
+}
+
+/// Given items in their new order, and the sorted slots of the table
+/// headers in and within each, hand out those same slots so that the
+/// items' tables come in the new order, keeping their order within each.
+fn reassign_slots<'a>(mut items: impl Iterator<Item = &'a mut Item>, groups: &[Vec<Slot>]) {
+    let mut pool: Vec<&Slot> = groups.iter().flatten().collect();
+    pool.sort_unstable();
+    let mut pool = pool.into_iter();
+    for group in groups {
+        let map: HashMap<usize, &Slot> = group
+            .iter()
+            .filter_map(|(old, _)| Some((*old, pool.next()?)))
+            .collect();
+        if let Some(item) = items.next() {
+            set_slots(item, &map);
+        }
+    }
+}
+
+fn set_slots(item: &mut Item, map: &HashMap<usize, &Slot>) {
+    match item {
+        Item::Table(table) => set_slots_within(table, map),
+        Item::ArrayOfTables(array) => {
+            for table in array.iter_mut() {
+                set_slots_within(table, map);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn set_slots_within(table: &mut Table, map: &HashMap<usize, &Slot>) {
+    let slot = table
+        .position()
+        .filter(|_| has_header(table))
+        .and_then(|old| map.get(&old));
+    if let Some((position, space)) = slot {
+        table.set_position(*position);
+        let decor = table.decor_mut();
+        let prefix = decor.prefix().and_then(RawString::as_str).unwrap_or("");
+        decor.set_prefix(format!("{}{}", space, prefix.trim_start()));
+    }
+    for (_, item) in table.iter_mut() {
+        set_slots(item, map);
+    }
+}
@@ -1,5 +1,6 @@
 use std::env;
 use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
 use std::process;
 use std::process::Output;
@@ -57,6 +58,33 @@ macro_rules! tomltest_get1 {
     };
 }
 
+/// Run a command on the input, with `{FILE}` in the arguments standing for
+/// the file, and check its output.
+macro_rules! tomltest_run {
+    ($name:ident, $input:expr, $args:expr, $expected:expr) => {
+        tomltest!($name, |mut t: TestCaseState| {
+            t.write_file($input);
+            t.cmd
+                .args($args.map(|a| a.replace("{FILE}", &t.filename())));
+            check_eq(&$expected, &t.expect_success());
+        });
+    };
+}
+
+macro_rules! tomltest_exit_code {
+    ($name:ident, $args:expr, $code:expr) => {
+        tomltest_exit_code!($name, INPUT, $args, $code);
+    };
+    ($name:ident, $input:expr, $args:expr, $code:expr) => {
+        tomltest!($name, |mut t: TestCaseState| {
+            t.write_file($input);
+            t.cmd
+                .args($args.map(|a| a.replace("{FILE}", &t.filename())));
+            t.expect_exit_code($code);
+        });
+    };
+}
+
 tomltest!(help_if_no_args, |mut t: TestCaseState| {
     check_contains("-h, --help", &t.expect_error());
 });
@@ -100,13 +128,499 @@ tomltest_get!(get_string_raw, ["--raw", "key"], "value\n");
 // TODO test `get --output-toml`
 
 tomltest_get_err!(get_invalid_query, [".bad"], "syntax error in query: .bad");
+#[rustfmt::skip]
+tomltest_get_err!(get_invalid_query_position, ["foo x"], "
+  foo x
+     ^ expected `.` or `[`
+hint: keys with characters like ' ' must be quoted; try `\"foo x\"`
+");
 tomltest_get_err_empty!(get_missing, ["nosuchkey"]);
 tomltest_get_err_empty!(get_missing_num, ["key[1]"]);
 
+tomltest_get!(get_default, ["nosuchkey", "--default", "x"], "\"x\"\n");
+tomltest_get!(
+    get_default_present,
+    ["key", "--default", "x"],
+    "\"value\"\n"
+);
+tomltest_get!(
+    get_default_raw,
+    ["-r", "key", "no", "--default=x"],
+    "value\nx\n"
+);
+#[rustfmt::skip]
+tomltest_get!(get_default_toml, ["--output-toml", "foo.z", "--default", "x"], r#"
+[foo]
+z = "x"
+"#);
+
+tomltest!(get_default_parse_error, |mut t: TestCaseState| {
+    t.write_file("a =\n");
+    t.cmd.args(["get", &t.filename(), "a", "--default", "x"]);
+    t.expect_exit_code(4);
+});
+
+const INPUT_FALSY: &str = r#"
+false = false
+true = true
+empty-array = []
+empty-inline = {}
+
+[empty-table]
+"#;
+
+#[rustfmt::skip]
+tomltest_exit_code!(get_exit_status_true, INPUT_FALSY, ["get", "-e", "{FILE}", "true"], 0);
+#[rustfmt::skip]
+tomltest_exit_code!(get_exit_status_false, INPUT_FALSY, ["get", "-e", "{FILE}", "false"], 1);
+#[rustfmt::skip]
+tomltest_exit_code!(get_exit_status_empty_array, INPUT_FALSY, ["get", "-e", "{FILE}", "empty-array"], 1);
+#[rustfmt::skip]
+tomltest_exit_code!(get_exit_status_empty_inline, INPUT_FALSY, ["get", "-e", "{FILE}", "empty-inline"], 1);
+#[rustfmt::skip]
+tomltest_exit_code!(get_exit_status_empty_table, INPUT_FALSY, ["get", "-e", "{FILE}", "empty-table"], 1);
+#[rustfmt::skip]
+tomltest_exit_code!(get_exit_status_nonempty_table, INPUT_FALSY, ["get", "-e", "{FILE}", "."], 0);
+#[rustfmt::skip]
+tomltest_exit_code!(get_exit_status_toml_false, INPUT_FALSY, ["get", "-e", "--output-toml", "{FILE}", "false"], 1);
+#[rustfmt::skip]
+tomltest_exit_code!(get_exit_status_toml_missing, INPUT_FALSY, ["get", "-e", "--output-toml", "{FILE}", "nosuchkey"], 1);
+#[rustfmt::skip]
+tomltest_exit_code!(get_toml_missing, INPUT_FALSY, ["get", "--output-toml", "{FILE}", "nosuchkey"], 1);
+
+tomltest!(exists, |mut t: TestCaseState| {
+    t.write_file(INPUT);
+    t.cmd.args(["exists", &t.filename(), "foo.y.yy"]);
+    check_eq("", &t.expect_success());
+});
+
+tomltest!(exists_false, |mut t: TestCaseState| {
+    t.write_file(INPUT_FALSY);
+    t.cmd.args(["exists", &t.filename(), "false"]);
+    check_eq("", &t.expect_success());
+});
+
+tomltest!(exists_missing, |mut t: TestCaseState| {
+    t.write_file(INPUT);
+    t.cmd.args(["exists", &t.filename(), "foo.z"]);
+    check_eq("", &t.expect_error());
+});
+
+tomltest_get!(get_type_int, ["--type=int", "int"], "17\n");
+tomltest_get!(get_type_bool, ["--type", "bool", "bool"], "true\n");
+tomltest_get!(
+    get_type_default,
+    ["--type=int", "nosuchkey", "--default=3"],
+    "3\n"
+);
+tomltest_get_err!(
+    get_type_mismatch,
+    ["--type=int", "key"],
+    "key: expected integer, found string"
+);
+tomltest!(get_type_mismatch_code, |mut t: TestCaseState| {
+    t.write_file(INPUT);
+    t.cmd.args(["get", &t.filename(), "--type=bool", "int"]);
+    t.expect_exit_code(5);
+});
+tomltest_get_err!(
+    get_type_bad_default,
+    ["--type=int", "nosuchkey", "--default=x"],
+    "invalid default for --type=int: x"
+);
+
+tomltest!(get_type_path, |mut t: TestCaseState| {
+    t.write_file("rel = \"sub/file\"\nabs = \"/abs\"\nhome = \"~/h\"\n");
+    t.cmd.env("HOME", "/home/someone");
+    t.cmd.args([
+        "get",
+        "-r",
+        "--type=path",
+        &t.filename(),
+        "rel",
+        "abs",
+        "home",
+    ]);
+    let dir = t.dir.path().display();
+    check_eq(
+        &format!("{dir}/sub/file\n/abs\n/home/someone/h\n"),
+        &t.expect_success(),
+    );
+});
+
+tomltest_get!(
+    get_multiple,
+    ["key", "int", "foo.x"],
+    "[\"value\",17,\"foo-x\"]\n"
+);
+tomltest_get!(get_multiple_raw, ["-r", "key", "int"], "value\n17\n");
+tomltest_get_err!(
+    get_multiple_missing,
+    ["key", "nosuchkey"],
+    "key not found: nosuchkey"
+);
+
+const INPUT_B: &str = r#"
+key = "value-b"
+"#;
+
+tomltest!(get_files, |mut t: TestCaseState| {
+    let a = t.write_file_named("a.toml", INPUT);
+    let b = t.write_file_named("b.toml", INPUT_B);
+    t.cmd.args(["get", "-f", &a, "-f", &b, "key"]);
+    check_eq(
+        &format!("{a}:\"value\"\n{b}:\"value-b\"\n"),
+        &t.expect_success(),
+    );
+});
+
+tomltest!(get_files_glob, |mut t: TestCaseState| {
+    let a = t.write_file_named("a.toml", INPUT);
+    let b = t.write_file_named("b.toml", INPUT_B);
+    let pattern = format!("{}/*.toml", t.dir.path().display());
+    t.cmd.args(["get", "--raw", &pattern, "key"]);
+    check_eq(&format!("{a}:value\n{b}:value-b\n"), &t.expect_success());
+});
+
+tomltest!(get_files_missing, |mut t: TestCaseState| {
+    t.write_file_named("a.toml", INPUT);
+    let b = t.write_file_named("b.toml", INPUT_B);
+    let pattern = format!("{}/*.toml", t.dir.path().display());
+    t.cmd.args(["get", "-f", &pattern, "int"]);
+    check_contains(&format!("{b}: key not found: int"), &t.expect_error());
+});
+
+tomltest!(get_files_skip_missing, |mut t: TestCaseState| {
+    let a = t.write_file_named("a.toml", INPUT);
+    t.write_file_named("b.toml", INPUT_B);
+    let pattern = format!("{}/*.toml", t.dir.path().display());
+    t.cmd.args(["get", "-f", &pattern, "--skip-missing", "int"]);
+    check_eq(&format!("{a}:17\n"), &t.expect_success());
+});
+
+tomltest!(get_with_filename, |mut t: TestCaseState| {
+    t.write_file(INPUT);
+    t.cmd.args(["get", "-H", &t.filename(), "int"]);
+    check_eq(&format!("{}:17\n", t.filename()), &t.expect_success());
+});
+
+tomltest!(get_parse_error, |mut t: TestCaseState| {
+    t.write_file("a = 1\nb = [1,,]\n");
+    t.cmd.args(["get", &t.filename(), "a"]);
+    let expected = format!(
+        "toml: {}:2:8: invalid array; expected `]`\
+       \n  |\
+       \n2 | b = [1,,]\
+       \n  |        ^\n",
+        t.filename()
+    );
+    check_eq(&expected, &t.expect_error());
+});
+
+tomltest!(get_parse_error_newline, |mut t: TestCaseState| {
+    t.write_file("a\n");
+    t.cmd.args(["get", &t.filename(), "a"]);
+    check_contains(
+        &format!("{}:1:2: expected `.`, `=`", t.filename()),
+        &t.expect_error(),
+    );
+});
+
+tomltest_exit_code!(exit_code_missing_key, ["get", "{FILE}", "nosuchkey"], 1);
+tomltest_exit_code!(exit_code_usage, ["get"], 2);
+tomltest_exit_code!(exit_code_query, ["get", "{FILE}", "a b"], 2);
+tomltest_exit_code!(exit_code_io, ["get", "{FILE}.nonexistent", "key"], 3);
+tomltest_exit_code!(exit_code_type_conflict, ["set", "{FILE}", "key[0]", "x"], 5);
+
+tomltest!(exit_code_parse, |mut t: TestCaseState| {
+    t.write_file("a =\n");
+    t.cmd.args(["get", &t.filename(), "a"]);
+    t.expect_exit_code(4);
+});
+
+tomltest!(get_io_error, |mut t: TestCaseState| {
+    t.cmd.args(["get", &t.filename(), "key"]);
+    check_eq(
+        &format!(
+            "toml: {}: No such file or directory (os error 2)\n",
+            t.filename()
+        ),
+        &t.expect_error(),
+    );
+});
+
+tomltest!(error_format_json_query, |mut t: TestCaseState| {
+    t.write_file(INPUT);
+    t.cmd
+        .args(["--error-format=json", "get", &t.filename(), "a..b"]);
+    let err: serde_json::Value = serde_json::from_str(&t.expect_error()).unwrap();
+    assert_eq!(
+        err,
+        serde_json::json!({
+            "kind": "query-syntax",
+            "message": "expected a key",
+            "path": null,
+            "query": "a..b",
+            "span": { "offset": 2 },
+        })
+    );
+});
+
+tomltest!(error_format_json_key_not_found, |mut t: TestCaseState| {
+    t.write_file(INPUT);
+    t.cmd
+        .args(["--error-format=json", "get", &t.filename(), "nosuchkey"]);
+    let err: serde_json::Value = serde_json::from_str(&t.expect_error()).unwrap();
+    assert_eq!(
+        err,
+        serde_json::json!({
+            "kind": "key-not-found",
+            "message": "key not found: nosuchkey",
+            "path": t.filename(),
+            "query": "nosuchkey",
+            "span": null,
+        })
+    );
+});
+
+tomltest!(error_format_json_parse, |mut t: TestCaseState| {
+    t.write_file("a = 1\nb = [1,,]\n");
+    t.cmd
+        .args(["get", &t.filename(), "a", "--error-format", "json"]);
+    let err: serde_json::Value = serde_json::from_str(&t.expect_error()).unwrap();
+    assert_eq!(
+        err,
+        serde_json::json!({
+            "kind": "parse",
+            "message": "invalid array; expected `]`",
+            "path": t.filename(),
+            "query": null,
+            "span": { "line": 2, "column": 8 },
+        })
+    );
+});
+
+const INPUT_TYPES: &str = r#"
+string = "s"
+integer = 1
+float = 1.5
+boolean = true
+offset-datetime = 1979-05-27T07:32:00Z
+local-datetime = 1979-05-27T07:32:00
+local-date = 1979-05-27
+local-time = 07:32:00
+array = [1, 2, 3]
+inline-table = { b = 1, a = 2 }
+
+[table]
+z = 1
+y.x = 2
+
+[[array-of-tables]]
+[[array-of-tables]]
+"#;
+
+#[rustfmt::skip]
+tomltest_run!(keys_table, INPUT_TYPES, ["keys", "{FILE}", "table"], "z\ny\n");
+#[rustfmt::skip]
+tomltest_run!(keys_inline_table, INPUT_TYPES, ["keys", "{FILE}", "inline-table"], "b\na\n");
+#[rustfmt::skip]
+tomltest_run!(length_array, INPUT_TYPES, ["length", "{FILE}", "array"], "3\n");
+#[rustfmt::skip]
+tomltest_run!(length_table, INPUT_TYPES, ["length", "{FILE}", "table"], "2\n");
+#[rustfmt::skip]
+tomltest_run!(length_array_of_tables, INPUT_TYPES, ["length", "{FILE}", "array-of-tables"], "2\n");
+
+#[rustfmt::skip]
+tomltest_run!(type_string, INPUT_TYPES, ["type", "{FILE}", "string"], "string\n");
+#[rustfmt::skip]
+tomltest_run!(type_integer, INPUT_TYPES, ["type", "{FILE}", "integer"], "integer\n");
+#[rustfmt::skip]
+tomltest_run!(type_float, INPUT_TYPES, ["type", "{FILE}", "float"], "float\n");
+#[rustfmt::skip]
+tomltest_run!(type_boolean, INPUT_TYPES, ["type", "{FILE}", "boolean"], "boolean\n");
+#[rustfmt::skip]
+tomltest_run!(type_offset_datetime, INPUT_TYPES, ["type", "{FILE}", "offset-datetime"], "offset-datetime\n");
+#[rustfmt::skip]
+tomltest_run!(type_local_datetime, INPUT_TYPES, ["type", "{FILE}", "local-datetime"], "local-datetime\n");
+#[rustfmt::skip]
+tomltest_run!(type_local_date, INPUT_TYPES, ["type", "{FILE}", "local-date"], "local-date\n");
+#[rustfmt::skip]
+tomltest_run!(type_local_time, INPUT_TYPES, ["type", "{FILE}", "local-time"], "local-time\n");
+#[rustfmt::skip]
+tomltest_run!(type_array, INPUT_TYPES, ["type", "{FILE}", "array"], "array\n");
+#[rustfmt::skip]
+tomltest_run!(type_inline_table, INPUT_TYPES, ["type", "{FILE}", "inline-table"], "inline-table\n");
+#[rustfmt::skip]
+tomltest_run!(type_table, INPUT_TYPES, ["type", "{FILE}", "table"], "table\n");
+#[rustfmt::skip]
+tomltest_run!(type_array_of_tables, INPUT_TYPES, ["type", "{FILE}", "array-of-tables"], "array-of-tables\n");
+
+tomltest!(keys_not_table, |mut t: TestCaseState| {
+    t.write_file(INPUT_TYPES);
+    t.cmd.args(["keys", &t.filename(), "array"]);
+    check_contains("array: expected table, found array", &t.expect_error());
+});
+
+tomltest!(list, |mut t: TestCaseState| {
+    t.write_file(INPUT);
+    t.cmd.args(["list", &t.filename()]);
+    #[rustfmt::skip]
+    check_eq(r#"key = "value"
+int = 17
+bool = true
+bare-Key_1 = "bare"
+"quoted key‽" = "quoted"
+"" = "empty"
+dotted.a = "dotted-a"
+dotted.b = "dotted-b"
+foo.x = "foo-x"
+foo.y.yy = "foo-yy"
+"#, &t.expect_success());
+});
+
+tomltest!(list_query, |mut t: TestCaseState| {
+    t.write_file(INPUT_TYPES);
+    t.cmd.args(["list", &t.filename(), "."]);
+    #[rustfmt::skip]
+    check_eq(r#"string = "s"
+integer = 1
+float = 1.5
+boolean = true
+offset-datetime = 1979-05-27T07:32:00Z
+local-datetime = 1979-05-27T07:32:00
+local-date = 1979-05-27
+local-time = 07:32:00
+array[0] = 1
+array[1] = 2
+array[2] = 3
+inline-table.b = 1
+inline-table.a = 2
+table.z = 1
+table.y.x = 2
+array-of-tables[0] = {}
+array-of-tables[1] = {}
+"#, &t.expect_success());
+});
+
+tomltest!(list_subtree, |mut t: TestCaseState| {
+    t.write_file("a = { \"x y\" = [0x10, 'lit'], e = [] }\nb = 1\n");
+    t.cmd.args(["list", &t.filename(), "a"]);
+    check_eq(
+        "a.\"x y\"[0] = 0x10\na.\"x y\"[1] = 'lit'\na.e = []\n",
+        &t.expect_success(),
+    );
+});
+
+tomltest!(list_multiline_string, |mut t: TestCaseState| {
+    t.write_file("s = \"\"\"\na \"quoted\"\nb\"\"\"\nl = '''\nc\n'''\n");
+    t.cmd.args(["list", &t.filename()]);
+    #[rustfmt::skip]
+    check_eq(r#"s = "a \"quoted\"\nb"
+l = "c\n"
+"#, &t.expect_success());
+});
+
+const LAYER_SYSTEM: &str = r#"
+name = "system"
+port = 1
+
+[db]
+host = "system-host"
+"#;
+
+const LAYER_USER: &str = r#"# user overrides
+name = "user"
+db.host = "user-host"
+"#;
+
+tomltest!(get_layered, |mut t: TestCaseState| {
+    let sys = t.write_file_named("system.toml", LAYER_SYSTEM);
+    let user = t.write_file_named("user.toml", LAYER_USER);
+    let project = format!("{}/nonexistent.toml", t.dir.path().display());
+    t.cmd.args(["get", "--layered", "-r"]);
+    t.cmd.args(["-f", &sys, "-f", &user, "-f", &project]);
+    t.cmd.args(["name", "port", "db.host"]);
+    check_eq("user\n1\nuser-host\n", &t.expect_success());
+});
+
+tomltest!(get_layered_table, |mut t: TestCaseState| {
+    // Tables aren't merged: `db.port` from the system file is hidden.
+    let sys = t.write_file_named("system.toml", "[db]\nhost = \"system-host\"\nport = 5432\n");
+    let user = t.write_file_named("user.toml", LAYER_USER);
+    t.cmd
+        .args(["get", "--layered", "-f", &sys, "-f", &user, "db"]);
+    check_eq("{\"host\":\"user-host\"}\n", &t.expect_success());
+});
+
+tomltest!(get_layered_show_origin, |mut t: TestCaseState| {
+    let sys = t.write_file_named("system.toml", LAYER_SYSTEM);
+    let user = t.write_file_named("user.toml", LAYER_USER);
+    t.cmd.args(["get", "--layered", "--show-origin"]);
+    t.cmd.args([
+        "-f",
+        &sys,
+        "-f",
+        &user,
+        "name",
+        "port",
+        "db.host",
+        "nosuchkey",
+    ]);
+    t.cmd.args(["--default", "x"]);
+    check_eq(
+        &format!("{user}:2\t\"user\"\n{sys}:3\t1\n{user}:3\t\"user-host\"\n(default)\t\"x\"\n"),
+        &t.expect_success(),
+    );
+});
+
+tomltest!(get_layered_missing, |mut t: TestCaseState| {
+    let sys = t.write_file_named("system.toml", LAYER_SYSTEM);
+    let user = t.write_file_named("user.toml", LAYER_USER);
+    t.cmd
+        .args(["get", "--layered", "-f", &sys, "-f", &user, "nosuchkey"]);
+    check_eq("", &t.expect_error());
+});
+
+const INPUT_SPANS: &str = r#"name = "x"  # comment
+
+[db]
+host = "h"
+ports = [
+  1, 2,
+]
+
+[server]
+listen.addr = "0.0.0.0"
+listen.port = 80
+dep = { version = "1.0", features = ["a"] }
+"#;
+
+tomltest!(get_show_span, |mut t: TestCaseState| {
+    t.write_file(INPUT_SPANS);
+    t.cmd.args(["get", "--show-span", "-r", &t.filename()]);
+    t.cmd.args(["name", "db", "db.ports[1]"]);
+    t.cmd.args(["server.listen", "server.dep.version"]);
+    let f = t.filename();
+    check_eq(
+        &format!(
+            "{f}:1:1-1:10\tx\n\
+             {f}:3:1-7:1\t{{\"host\":\"h\",\"ports\":[1,2]}}\n\
+             {f}:6:6-6:6\t2\n\
+             {f}:10:1-11:16\t{{\"addr\":\"0.0.0.0\",\"port\":80}}\n\
+             {f}:12:9-12:23\t1.0\n"
+        ),
+        &t.expect_success(),
+    );
+});
+
 macro_rules! tomltest_set {
     ($name:ident, $args:expr, $expected:expr) => {
+        tomltest_set!($name, INITIAL, $args, $expected);
+    };
+    ($name:ident, $input:expr, $args:expr, $expected:expr) => {
         tomltest!($name, |mut t: TestCaseState| {
-            t.write_file(INITIAL);
+            t.write_file($input);
             t.cmd.args(["set", &t.filename()]).args($args);
             check_eq(&$expected, &t.expect_success());
         });
@@ -141,14 +655,1025 @@ tomltest_set!(set_string_toplevel, ["foo", "bar"], format!(
 r#"foo = "bar"
 {INITIAL}"#));
 
+#[rustfmt::skip]
+tomltest_set!(set_style_table, ["foo.bar.baz", "q"], format!(
+r#"{INITIAL}
+[foo.bar]
+baz = "q"
+"#));
+
+#[rustfmt::skip]
+tomltest_set!(set_style_inline, ["--style=inline", "foo.bar.baz", "q"], format!(
+r#"foo = {{ bar = {{ baz = "q" }} }}
+{INITIAL}"#));
+
+#[rustfmt::skip]
+tomltest_set!(set_style_dotted, ["--style=dotted", "x.foo.bar", "q"], format!(
+r#"{INITIAL}foo.bar = "q"
+"#));
+
+#[rustfmt::skip]
+tomltest_set!(set_in_inline_table, "x = { y = 2 }\n", ["x.n", "q"], "x = { y = 2, n = \"q\" }\n");
+
+const INPUT_FORMATS: &str = r#"name = 'x'  # keep
+hex = 0xFF_FF
+big = 1_000_000
+arr = [ "a", "b" ]
+"#;
+
+#[rustfmt::skip]
+tomltest_set!(set_keeps_decor, INPUT_FORMATS, ["name", "y"], r#"name = 'y'  # keep
+hex = 0xFF_FF
+big = 1_000_000
+arr = [ "a", "b" ]
+"#);
+#[rustfmt::skip]
+tomltest_set!(set_keeps_decor_array, INPUT_FORMATS, ["arr[1]", "c"], r#"name = 'x'  # keep
+hex = 0xFF_FF
+big = 1_000_000
+arr = [ "a", "c" ]
+"#);
+#[rustfmt::skip]
+tomltest_set!(set_literal_fallback, INPUT_FORMATS, ["name", "it's"], r#"name = "it's"  # keep
+hex = 0xFF_FF
+big = 1_000_000
+arr = [ "a", "b" ]
+"#);
+#[rustfmt::skip]
+tomltest_set!(set_keeps_hex, INPUT_FORMATS, ["hex", "4095"], r#"name = 'x'  # keep
+hex = 0x0F_FF
+big = 1_000_000
+arr = [ "a", "b" ]
+"#);
+#[rustfmt::skip]
+tomltest_set!(set_keeps_grouping, INPUT_FORMATS, ["big", "1234567"], r#"name = 'x'  # keep
+hex = 0xFF_FF
+big = 1_234_567
+arr = [ "a", "b" ]
+"#);
+#[rustfmt::skip]
+tomltest_set!(set_integer_as_string, INPUT_FORMATS, ["--string-style=basic", "big", "5"], r#"name = 'x'  # keep
+hex = 0xFF_FF
+big = "5"
+arr = [ "a", "b" ]
+"#);
+#[rustfmt::skip]
+tomltest_set!(set_string_style_multiline, INPUT_FORMATS, ["--string-style=multiline", "name", "a\nb"], r#"name = """
+a
+b"""  # keep
+hex = 0xFF_FF
+big = 1_000_000
+arr = [ "a", "b" ]
+"#);
+
+tomltest!(set_string_style_literal_fails, |mut t: TestCaseState| {
+    t.write_file(INPUT_FORMATS);
+    t.cmd.args([
+        "set",
+        "--string-style=literal",
+        &t.filename(),
+        "name",
+        "it's",
+    ]);
+    check_contains(
+        "can't be written as a literal string",
+        &t.expect_exit_code(2),
+    );
+});
+
+tomltest!(set_new_table_placement, |mut t: TestCaseState| {
+    t.write_file("[x]\na = 1\n\n[x.sub]\nb = 2\n\n[y]\nc = 3\n");
+    t.cmd.args(["set", &t.filename(), "x.new.k", "v"]);
+    check_eq(
+        "[x]\na = 1\n\n[x.sub]\nb = 2\n\n[x.new]\nk = \"v\"\n\n[y]\nc = 3\n",
+        &t.expect_success(),
+    );
+});
+
+tomltest!(set_files, |mut t: TestCaseState| {
+    let a = t.write_file_named("a.toml", INITIAL);
+    let b = t.write_file_named("b.toml", "[x]\ny = \"new\"\n");
+    t.cmd.args(["set", "-f", &a, "-f", &b, "x.y", "new"]);
+    check_eq(
+        &format!("changed: {a}\nunchanged: {b}\n1 changed, 1 unchanged, 0 failed\n"),
+        &t.expect_success(),
+    );
+    check_eq("\n[x]\ny = \"new\"\n", &fs::read_to_string(&a).unwrap());
+});
+
+tomltest!(set_files_from_stdin, |mut t: TestCaseState| {
+    let a = t.write_file_named("a.toml", INITIAL);
+    let b = t.write_file_named("b.toml", "x =\n");
+    t.cmd.args(["set", "--files-from", "-", "x.y", "new"]);
+    t.cmd.stdin(process::Stdio::piped());
+    let mut child = t.cmd.stdout(process::Stdio::piped()).spawn().unwrap();
+    write!(child.stdin.take().unwrap(), "{a}\n{b}\n").unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(!out.status.success());
+    check_eq(
+        &format!("changed: {a}\n1 changed, 0 unchanged, 1 failed\n"),
+        str::from_utf8(&out.stdout).unwrap(),
+    );
+    check_eq("x =\n", &fs::read_to_string(&b).unwrap());
+});
+
+#[cfg(unix)]
+tomltest!(set_files_symlink, |mut t: TestCaseState| {
+    let a = t.write_file_named("a.toml", INITIAL);
+    let link = t.dir.path().join("link.toml");
+    std::os::unix::fs::symlink(&a, &link).unwrap();
+    t.cmd
+        .args(["set", "-f", link.to_str().unwrap(), "x.y", "new"]);
+    t.expect_success();
+    assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+    check_eq("\n[x]\ny = \"new\"\n", &fs::read_to_string(&a).unwrap());
+    let names: Vec<_> = fs::read_dir(t.dir.path()).unwrap().collect();
+    assert_eq!(2, names.len());
+});
+
+tomltest!(set_files_root, |mut t: TestCaseState| {
+    let a = t.write_file_named("a.toml", INITIAL);
+    t.cmd.args(["set", "-f", &a, ".", "x"]);
+    check_contains("can't replace the whole document", &t.expect_exit_code(2));
+    check_eq(INITIAL, &fs::read_to_string(&a).unwrap());
+});
+
+tomltest!(set_pattern_many_files, |mut t: TestCaseState| {
+    let a = t.write_file_named("a.toml", INITIAL);
+    t.write_file_named("b.toml", INITIAL);
+    let pattern = format!("{}/*.toml", t.dir.path().display());
+    t.cmd.args(["set", &pattern, "x.y", "new"]);
+    check_contains("--file", &t.expect_exit_code(2));
+    check_eq(INITIAL, &fs::read_to_string(&a).unwrap());
+});
+
+const INPUT_COMMENTS: &str = r#"# header
+
+# the name
+# of the thing
+name = "x"  # trailing
+version = "1"
+
+[db]
+ports = [
+  # first
+  1,
+  2,
+]
+dep = { version = "1.0" }
+"#;
+
+#[rustfmt::skip]
+tomltest_run!(comment_get, INPUT_COMMENTS, ["comment", "get", "{FILE}", "name"], "the name\nof the thing\n");
+#[rustfmt::skip]
+tomltest_run!(comment_get_trailing, INPUT_COMMENTS, ["comment", "get", "{FILE}", "--trailing", "name"], "trailing\n");
+#[rustfmt::skip]
+tomltest_run!(comment_get_none, INPUT_COMMENTS, ["comment", "get", "{FILE}", "version"], "");
+#[rustfmt::skip]
+tomltest_run!(comment_get_array_element, INPUT_COMMENTS, ["comment", "get", "{FILE}", "db.ports[0]"], "first\n");
+
+tomltest!(comment_set, |mut t: TestCaseState| {
+    t.write_file(INPUT_COMMENTS);
+    t.cmd
+        .args(["comment", "set", &t.filename(), "db.ports[1]", "second"]);
+    check_contains("  1,\n  # second\n  2,\n", &t.expect_success());
+});
+
+tomltest!(comment_set_replace, |mut t: TestCaseState| {
+    t.write_file(INPUT_COMMENTS);
+    t.cmd
+        .args(["comment", "set", &t.filename(), "name", "managed by bot"]);
+    check_contains(
+        "# header\n\n# managed by bot\nname = \"x\"  # trailing\n",
+        &t.expect_success(),
+    );
+});
+
+tomltest!(comment_set_trailing, |mut t: TestCaseState| {
+    t.write_file(INPUT_COMMENTS);
+    t.cmd.args([
+        "comment",
+        "set",
+        "--trailing",
+        &t.filename(),
+        "version",
+        "bumped",
+    ]);
+    check_contains("version = \"1\" # bumped\n", &t.expect_success());
+});
+
+tomltest!(comment_set_remove, |mut t: TestCaseState| {
+    t.write_file(INPUT_COMMENTS);
+    t.cmd.args(["comment", "set", &t.filename(), "name", ""]);
+    check_contains("# header\n\nname = \"x\"", &t.expect_success());
+});
+
+tomltest!(comment_inline_table, |mut t: TestCaseState| {
+    t.write_file(INPUT_COMMENTS);
+    t.cmd
+        .args(["comment", "set", &t.filename(), "db.dep.version", "x"]);
+    check_contains(
+        "comments can't go inside an inline table",
+        &t.expect_exit_code(5),
+    );
+});
+
 // TODO test `set` on string with newlines and other fun characters
 // TODO test `set` when existing value is an array, table, or array of tables
 // TODO test `set` inside existing array or inline table
 // TODO test `set` inside existing array of tables
 
+const INPUT_UNFORMATTED: &str = r#"
+  name="x"   # the name
+deps = [ "a","b" ]
+list = [ 1, # one
+  2 ]
+[ t ]
+x = { a=1 }
+"#;
+
+const INPUT_FORMATTED: &str = r#"name = "x" # the name
+deps = ["a", "b"]
+list = [
+    1, # one
+    2,
+]
+
+[t]
+x = { a = 1 }
+"#;
+
+tomltest!(fmt, |mut t: TestCaseState| {
+    t.write_file(INPUT_UNFORMATTED);
+    t.cmd.args(["fmt", &t.filename()]);
+    check_eq("", &t.expect_success());
+    check_eq(INPUT_FORMATTED, &fs::read_to_string(t.filename()).unwrap());
+});
+
+tomltest!(fmt_width, |mut t: TestCaseState| {
+    t.write_file("deps = [\"aaa\", \"bbb\"]\n");
+    t.cmd.args(["fmt", "--width=20", &t.filename()]);
+    t.expect_success();
+    check_eq(
+        "deps = [\n    \"aaa\",\n    \"bbb\",\n]\n",
+        &fs::read_to_string(t.filename()).unwrap(),
+    );
+});
+
+tomltest!(fmt_check, |mut t: TestCaseState| {
+    t.write_file(INPUT_UNFORMATTED);
+    t.cmd.args(["fmt", "--check", &t.filename()]);
+    let stdout = t.expect_exit_code_stdout(1);
+    check_contains("\n-deps = [ \"a\",\"b\" ]\n", &stdout);
+    check_contains("\n+deps = [\"a\", \"b\"]\n", &stdout);
+    check_eq(
+        INPUT_UNFORMATTED,
+        &fs::read_to_string(t.filename()).unwrap(),
+    );
+});
+
+tomltest!(fmt_check_formatted, |mut t: TestCaseState| {
+    t.write_file(INPUT_FORMATTED);
+    t.cmd.args(["fmt", "--check", &t.filename()]);
+    check_eq("", &t.expect_success());
+});
+
+const INPUT_UNSORTED: &str = r#"# Top comment
+
+zeta = 1
+# about alpha
+alpha = 2 # trailing
+list = ["b", 10, "a", 2.5]
+t = {b=1, a=2}
+
+[[bin]]
+name = "zed"
+
+[bin.meta]
+x = 1
+
+[[bin]]
+name = "abc"
+
+[b]
+y = 1
+x = 2
+"#;
+
+tomltest!(sort, |mut t: TestCaseState| {
+    t.write_file(INPUT_UNSORTED);
+    t.cmd.args(["sort", &t.filename()]);
+    check_eq("", &t.expect_success());
+    check_eq(
+        r#"# Top comment
+
+# about alpha
+alpha = 2 # trailing
+list = ["b", 10, "a", 2.5]
+t = {b=1, a=2}
+zeta = 1
+
+[b]
+y = 1
+x = 2
+
+[[bin]]
+name = "zed"
+
+[bin.meta]
+x = 1
+
+[[bin]]
+name = "abc"
+"#,
+        &fs::read_to_string(t.filename()).unwrap(),
+    );
+});
+
+tomltest!(sort_recursive, |mut t: TestCaseState| {
+    t.write_file("[b]\ny = 1\nx = {d=1, c=2}\n\n[a]\nz = 1\n");
+    t.cmd.args(["sort", "--recursive", &t.filename()]);
+    t.expect_success();
+    check_eq(
+        "[a]\nz = 1\n\n[b]\nx = {c=2, d=1}\ny = 1\n",
+        &fs::read_to_string(t.filename()).unwrap(),
+    );
+});
+
+tomltest!(sort_array, |mut t: TestCaseState| {
+    t.write_file("list = [\"b\", 10, \"a\", 2.5]\n");
+    t.cmd.args(["sort", &t.filename(), "list"]);
+    t.expect_success();
+    check_eq(
+        "list = [2.5, 10, \"a\", \"b\"]\n",
+        &fs::read_to_string(t.filename()).unwrap(),
+    );
+});
+
+tomltest!(sort_array_multiline, |mut t: TestCaseState| {
+    t.write_file("ml = [\n  \"y\",\n  # about x\n  \"x\",\n]\n");
+    t.cmd.args(["sort", &t.filename(), "ml"]);
+    t.expect_success();
+    check_eq(
+        "ml = [\n  # about x\n  \"x\",\n  \"y\",\n]\n",
+        &fs::read_to_string(t.filename()).unwrap(),
+    );
+});
+
+tomltest!(sort_by, |mut t: TestCaseState| {
+    t.write_file(INPUT_UNSORTED);
+    t.cmd.args(["sort", "--by=name", &t.filename(), "bin"]);
+    t.expect_success();
+    check_contains(
+        r#"[[bin]]
+name = "abc"
+
+[[bin]]
+name = "zed"
+
+[bin.meta]
+x = 1
+
+[b]"#,
+        &fs::read_to_string(t.filename()).unwrap(),
+    );
+});
+
+tomltest!(sort_array_of_tables_needs_by, |mut t: TestCaseState| {
+    t.write_file(INPUT_UNSORTED);
+    t.cmd.args(["sort", &t.filename(), "bin"]);
+    check_contains("--by", &t.expect_exit_code(2));
+});
+
+tomltest!(sort_check, |mut t: TestCaseState| {
+    t.write_file(INPUT_UNSORTED);
+    t.cmd.args(["sort", "--check", &t.filename(), "b"]);
+    let stdout = t.expect_exit_code_stdout(1);
+    check_contains("\n+x = 2\n y = 1\n-x = 2\n", &stdout);
+    check_eq(INPUT_UNSORTED, &fs::read_to_string(t.filename()).unwrap());
+});
+
+const INPUT_MOVE: &str = r#"[server]
+# the address
+addr = "0.0.0.0:80" # default
+port = 80
+
+[server.tls]
+cert = "x.pem"
+
+[other]
+dep = { version = "1" }
+"#;
+
+tomltest!(mv, |mut t: TestCaseState| {
+    t.write_file(INPUT_MOVE);
+    t.cmd
+        .args(["mv", &t.filename(), "server.addr", "server.listen.address"]);
+    check_eq(
+        r#"[server]
+port = 80
+
+[server.tls]
+cert = "x.pem"
+
+[server.listen]
+# the address
+address = "0.0.0.0:80" # default
+
+[other]
+dep = { version = "1" }
+"#,
+        &t.expect_success(),
+    );
+});
+
+tomltest!(mv_table, |mut t: TestCaseState| {
+    t.write_file(INPUT_MOVE);
+    t.cmd.args(["mv", &t.filename(), "server.tls", "other.tls"]);
+    check_contains(
+        r#"[other]
+dep = { version = "1" }
+
+[other.tls]
+cert = "x.pem"
+"#,
+        &t.expect_success(),
+    );
+});
+
+tomltest!(mv_table_to_inline, |mut t: TestCaseState| {
+    t.write_file(INPUT_MOVE);
+    t.cmd
+        .args(["mv", &t.filename(), "server.tls", "other.dep.tls"]);
+    check_contains(
+        r#"dep = { version = "1", tls = { cert = "x.pem" } }"#,
+        &t.expect_success(),
+    );
+});
+
+tomltest!(mv_exists, |mut t: TestCaseState| {
+    t.write_file(INPUT_MOVE);
+    t.cmd
+        .args(["mv", &t.filename(), "server.addr", "server.port"]);
+    check_contains("--force", &t.expect_exit_code(2));
+});
+
+tomltest!(mv_force, |mut t: TestCaseState| {
+    t.write_file(INPUT_MOVE);
+    t.cmd
+        .args(["mv", "--force", &t.filename(), "server.addr", "server.port"]);
+    check_contains(
+        r#"[server]
+# the address
+port = "0.0.0.0:80" # default
+
+[server.tls]"#,
+        &t.expect_success(),
+    );
+});
+
+tomltest!(mv_force_root, |mut t: TestCaseState| {
+    t.write_file(INPUT_MOVE);
+    t.cmd
+        .args(["mv", "--force", &t.filename(), "server.tls", "."]);
+    check_contains("can't replace the whole document", &t.expect_exit_code(2));
+});
+
+tomltest!(mv_missing, |mut t: TestCaseState| {
+    t.write_file(INPUT_MOVE);
+    t.cmd.args(["mv", &t.filename(), "server.nope", "server.x"]);
+    t.expect_exit_code(1);
+});
+
+const INPUT_COPY: &str = r#"[profile.release]
+# keep it small
+opt-level = "z"
+
+[dependencies]
+serde = { version = "1.0", features = ["derive"] }
+
+[dependencies.big]
+version = "2"
+"#;
+
+tomltest!(cp, |mut t: TestCaseState| {
+    t.write_file(INPUT_COPY);
+    t.cmd.args([
+        "cp",
+        &t.filename(),
+        "profile.release",
+        &t.filename(),
+        "profile.bench",
+    ]);
+    check_contains(
+        r#"opt-level = "z"
+
+[profile.bench]
+# keep it small
+opt-level = "z"
+
+[dependencies]"#,
+        &t.expect_success(),
+    );
+});
+
+tomltest!(cp_other_file, |mut t: TestCaseState| {
+    t.write_file(INPUT_COPY);
+    let dst = t.write_file_named("dst.toml", "[dependencies]\nanyhow = \"1\"\n");
+    t.cmd.args([
+        "cp",
+        &t.filename(),
+        "dependencies.serde",
+        &dst,
+        "dependencies.serde",
+    ]);
+    check_eq(
+        r#"[dependencies]
+anyhow = "1"
+serde = { version = "1.0", features = ["derive"] }
+"#,
+        &t.expect_success(),
+    );
+});
+
+tomltest!(cp_to_table, |mut t: TestCaseState| {
+    t.write_file(INPUT_COPY);
+    t.cmd.args([
+        "cp",
+        "--force",
+        &t.filename(),
+        "dependencies.serde",
+        &t.filename(),
+        "dependencies.big",
+    ]);
+    check_contains(
+        r#"[dependencies.big]
+version = "1.0"
+features = ["derive"]
+"#,
+        &t.expect_success(),
+    );
+});
+
+tomltest!(cp_to_inline, |mut t: TestCaseState| {
+    t.write_file(INPUT_COPY);
+    t.cmd.args([
+        "cp",
+        "--force",
+        &t.filename(),
+        "dependencies.big",
+        &t.filename(),
+        "dependencies.serde",
+    ]);
+    check_contains(r#"serde = { version = "2" }"#, &t.expect_success());
+});
+
+tomltest!(cp_exists, |mut t: TestCaseState| {
+    t.write_file(INPUT_COPY);
+    t.cmd.args([
+        "cp",
+        &t.filename(),
+        "dependencies.big",
+        &t.filename(),
+        "dependencies.serde",
+    ]);
+    check_contains("--force", &t.expect_exit_code(2));
+});
+
+tomltest!(cp_force_root, |mut t: TestCaseState| {
+    t.write_file(INPUT_COPY);
+    t.cmd.args([
+        "cp",
+        "--force",
+        &t.filename(),
+        "dependencies",
+        &t.filename(),
+        ".",
+    ]);
+    check_contains("can't replace the whole document", &t.expect_exit_code(2));
+});
+
+const INPUT_CONVERT: &str = r#"[dependencies]
+# serialization
+serde = { version = "1.0", features = ["derive"] } # pinned
+toml = "0.8"
+
+[[bin]]
+name = "a"
+
+# second
+[[bin]]
+name = "b"
+"#;
+
+tomltest!(convert_to_table, |mut t: TestCaseState| {
+    t.write_file(INPUT_CONVERT);
+    t.cmd
+        .args(["convert", "--to=table", &t.filename(), "dependencies.serde"]);
+    check_contains(
+        r#"[dependencies]
+toml = "0.8"
+
+# serialization
+[dependencies.serde] # pinned
+version = "1.0"
+features = ["derive"]
+
+[[bin]]"#,
+        &t.expect_success(),
+    );
+});
+
+tomltest!(convert_to_dotted, |mut t: TestCaseState| {
+    t.write_file(INPUT_CONVERT);
+    t.cmd.args([
+        "convert",
+        "--to=dotted",
+        &t.filename(),
+        "dependencies.serde",
+    ]);
+    check_contains(
+        r#"[dependencies]
+# serialization
+serde.version = "1.0"
+serde.features = ["derive"] # pinned
+toml = "0.8"
+"#,
+        &t.expect_success(),
+    );
+});
+
+tomltest!(convert_to_inline, |mut t: TestCaseState| {
+    t.write_file("[a]\nx = 1\n\n# about b\n[a.b]\ny = 2\n");
+    t.cmd.args(["convert", "--to=inline", &t.filename(), "a.b"]);
+    check_eq(
+        "[a]\nx = 1\n# about b\nb = { y = 2 }\n",
+        &t.expect_success(),
+    );
+});
+
+tomltest!(convert_array_of_tables, |mut t: TestCaseState| {
+    t.write_file(INPUT_CONVERT);
+    t.cmd.args(["convert", "--to=inline", &t.filename(), "bin"]);
+    check_contains(
+        r#"bin = [
+    { name = "a" },
+
+    # second
+    { name = "b" },
+]
+"#,
+        &t.expect_success(),
+    );
+});
+
+tomltest!(convert_array_to_tables, |mut t: TestCaseState| {
+    t.write_file("[a]\nbin = [\n    { name = \"a\" },\n    # second\n    { name = \"b\" },\n]\n");
+    t.cmd
+        .args(["convert", "--to=table", &t.filename(), "a.bin"]);
+    check_eq(
+        "[a]\n\n[[a.bin]]\nname = \"a\"\n\n# second\n[[a.bin]]\nname = \"b\"\n",
+        &t.expect_success(),
+    );
+});
+
+tomltest!(convert_to_dotted_ending_in_table, |mut t: TestCaseState| {
+    t.write_file("[p]\nx = 0\n\n# lead\n[p.a] # note\nb = 1\nc.d = 2\n");
+    t.cmd.args(["convert", "--to=dotted", &t.filename(), "p.a"]);
+    check_eq(
+        "[p]\nx = 0\n# lead\n# note\na.b = 1\na.c.d = 2\n",
+        &t.expect_success(),
+    );
+});
+
+tomltest!(convert_not_table, |mut t: TestCaseState| {
+    t.write_file(INPUT_CONVERT);
+    t.cmd.args(["convert", "--to=dotted", &t.filename(), "bin"]);
+    check_contains("dotted", &t.expect_exit_code(5));
+});
+
+const SCHEMA: &str = r#"{
+  "type": "object",
+  "properties": {
+    "server": {
+      "type": "object",
+      "properties": {
+        "host": { "type": "string" },
+        "port": { "type": "integer" }
+      },
+      "required": ["host"],
+      "additionalProperties": false
+    }
+  }
+}"#;
+
+tomltest!(validate, |mut t: TestCaseState| {
+    t.write_file("[server]\nport = \"80\"\nextra = true\n");
+    let schema = t.write_file_named("schema.json", SCHEMA);
+    t.cmd.args(["validate", "--schema", &schema, &t.filename()]);
+    let stdout = t.expect_exit_code_stdout(1);
+    let file = t.filename();
+    check_eq(
+        &format!(
+            "\
+{file}:1:1: server: \"host\" is a required property
+{file}:2:1: server.port: \"80\" is not of type \"integer\"
+{file}:3:1: server.extra: Additional properties are not allowed ('extra' was unexpected)
+"
+        ),
+        &stdout,
+    );
+});
+
+tomltest!(validate_no_span, |mut t: TestCaseState| {
+    t.write_file("x = 1\n\n[a]\nb.c = 1\n");
+    let schema = r#"{"required": ["name"], "properties": {"a": {"required": ["z"]}}}"#;
+    let schema = t.write_file_named("schema.json", schema);
+    t.cmd.args(["validate", "--schema", &schema, &t.filename()]);
+    let stdout = t.expect_exit_code_stdout(1);
+    let file = t.filename();
+    check_eq(
+        &format!(
+            "\
+{file}: .: \"name\" is a required property
+{file}:3:1: a: \"z\" is a required property
+"
+        ),
+        &stdout,
+    );
+});
+
+tomltest!(validate_ok, |mut t: TestCaseState| {
+    t.write_file("[server]\nhost = \"localhost\"\nport = 80\n");
+    let schema = t.write_file_named("schema.json", SCHEMA);
+    t.cmd.args(["validate", "--schema", &schema, &t.filename()]);
+    check_eq("", &t.expect_success());
+});
+
+tomltest!(validate_datetime, |mut t: TestCaseState| {
+    t.write_file("at = 1979-05-27T07:32:00Z\nbad = \"x\"\n");
+    let schema = r#"{
+  "$schema": "http://json-schema.org/draft-07/schema#",
+  "properties": {
+    "at": { "type": "string", "format": "date-time" },
+    "bad": { "type": "string", "format": "date-time" }
+  }
+}"#;
+    let schema = t.write_file_named("schema.json", schema);
+    t.cmd.args(["validate", "--schema", &schema, &t.filename()]);
+    check_eq(
+        &format!("{}:2:1: bad: \"x\" is not a \"date-time\"\n", t.filename()),
+        &t.expect_exit_code_stdout(1),
+    );
+});
+
+tomltest!(validate_unexpected_keys, |mut t: TestCaseState| {
+    t.write_file("[server]\nhost = \"h\"\nzz = 1\naa = 2\n");
+    let schema = t.write_file_named("schema.json", SCHEMA);
+    t.cmd.args(["validate", "--schema", &schema, &t.filename()]);
+    let file = t.filename();
+    check_eq(
+        &format!(
+            "\
+{file}:3:1: server.zz: Additional properties are not allowed ('zz' was unexpected)
+{file}:4:1: server.aa: Additional properties are not allowed ('aa' was unexpected)
+"
+        ),
+        &t.expect_exit_code_stdout(1),
+    );
+});
+
+tomltest!(validate_ref_file, |mut t: TestCaseState| {
+    t.write_file("port = \"80\"\n");
+    t.write_file_named("port.json", r#"{"type": "integer"}"#);
+    let schema = r#"{"properties": {"port": {"$ref": "port.json"}}}"#;
+    let schema = t.write_file_named("schema.json", schema);
+    t.cmd.args(["validate", "--schema", &schema, &t.filename()]);
+    let stdout = t.expect_exit_code_stdout(1);
+    check_contains("port: \"80\" is not of type \"integer\"", &stdout);
+});
+
+tomltest!(validate_bad_schema, |mut t: TestCaseState| {
+    t.write_file("");
+    let schema = t.write_file_named("schema.json", "{");
+    t.cmd.args(["validate", "--schema", &schema, &t.filename()]);
+    check_contains("invalid schema", &t.expect_exit_code(2));
+});
+
+tomltest!(set_schema_typed, |mut t: TestCaseState| {
+    t.write_file("[server]\nhost = \"localhost\"\nport = \"80\"\n");
+    let schema = t.write_file_named("schema.json", SCHEMA);
+    t.cmd.args([
+        "set",
+        "--schema",
+        &schema,
+        &t.filename(),
+        "server.port",
+        "8080",
+    ]);
+    check_eq(
+        "[server]\nhost = \"localhost\"\nport = 8080\n",
+        &t.expect_success(),
+    );
+});
+
+tomltest!(set_schema_string, |mut t: TestCaseState| {
+    t.write_file("[server]\nhost = 1\n");
+    let schema = t.write_file_named("schema.json", SCHEMA);
+    t.cmd.args([
+        "set",
+        "--schema",
+        &schema,
+        &t.filename(),
+        "server.host",
+        "2",
+    ]);
+    check_eq("[server]\nhost = \"2\"\n", &t.expect_success());
+});
+
+tomltest!(set_schema_unknown_key, |mut t: TestCaseState| {
+    t.write_file("[server]\nhost = \"localhost\"\n");
+    let schema = t.write_file_named("schema.json", SCHEMA);
+    t.cmd.args([
+        "set",
+        "--schema",
+        &schema,
+        &t.filename(),
+        "server.extra",
+        "x",
+    ]);
+    check_contains(
+        "server.extra: Additional properties are not allowed",
+        &t.expect_exit_code(5),
+    );
+});
+
+tomltest!(set_schema_another_unknown_key, |mut t: TestCaseState| {
+    // `extra` is already unexpected, and sorts before the new key.
+    t.write_file("[server]\nhost = \"localhost\"\nextra = 1\n");
+    let schema = t.write_file_named("schema.json", SCHEMA);
+    t.cmd.args([
+        "set",
+        "--schema",
+        &schema,
+        &t.filename(),
+        "server.prot",
+        "80",
+    ]);
+    check_contains(
+        "server.prot: Additional properties are not allowed ('prot' was unexpected)",
+        &t.expect_exit_code(5),
+    );
+});
+
+tomltest!(set_schema_files, |mut t: TestCaseState| {
+    let a = t.write_file_named("a.toml", "[server]\nhost = \"a\"\nport = 80\n");
+    let schema = t.write_file_named("schema.json", SCHEMA);
+    t.cmd
+        .args(["set", "--schema", &schema, "-f", &a, "server.port", "high"]);
+    t.expect_exit_code(5);
+    check_eq(
+        "[server]\nhost = \"a\"\nport = 80\n",
+        &fs::read_to_string(&a).unwrap(),
+    );
+});
+
+tomltest!(set_schema_files_unchanged, |mut t: TestCaseState| {
+    let a = t.write_file_named("a.toml", "debug = true # keep\n");
+    let schema = r#"{"properties": {"debug": {"type": "boolean"}}}"#;
+    let schema = t.write_file_named("schema.json", schema);
+    t.cmd
+        .args(["set", "--schema", &schema, "-f", &a, "debug", "true"]);
+    check_eq(
+        &format!("unchanged: {a}\n0 changed, 1 unchanged, 0 failed\n"),
+        &t.expect_success(),
+    );
+});
+
+tomltest!(set_schema_existing_violation, |mut t: TestCaseState| {
+    // The port was already not an integer; a different non-integer is no
+    // new violation.
+    t.write_file("[server]\nhost = \"a\"\nport = \"80\"\n");
+    let schema = t.write_file_named("schema.json", SCHEMA);
+    t.cmd.args([
+        "set",
+        "--schema",
+        &schema,
+        &t.filename(),
+        "server.port",
+        "high",
+    ]);
+    check_eq(
+        "[server]\nhost = \"a\"\nport = \"high\"\n",
+        &t.expect_success(),
+    );
+});
+
+const INPUT_LINT: &str = "\
+Name = \"a\"
+name = \"b\"
+mix = [1, \"two\"]
+pi = 3.14159265358979323846264
+s = \"\"\"
+kept  \"\"\"
+
+[a]
+x = 1  
+
+[b]
+y = 2
+
+[a.c]
+z = 3
+";
+
+tomltest!(check, |mut t: TestCaseState| {
+    t.write_file(INPUT_LINT);
+    t.cmd.args(["check", &t.filename()]);
+    let stdout = t.expect_exit_code_stdout(1);
+    let file = t.filename();
+    check_eq(
+        &format!(
+            "\
+{file}:2:1: key-case: key `name` differs only by case from `Name`
+{file}:3:7: mixed-array: array `mix` mixes integer and string
+{file}:4:6: float-precision: float `pi` can't be stored exactly as written, and is read as 3.141592653589793
+{file}:9:6: trailing-whitespace: trailing whitespace
+{file}:14:1: table-order: table `a.c` is apart from the rest of `a`, after `b`
+"
+        ),
+        &stdout,
+    );
+});
+
+tomltest!(check_only_json, |mut t: TestCaseState| {
+    t.write_file(INPUT_LINT);
+    t.cmd.args([
+        "check",
+        "--format",
+        "json",
+        "--only",
+        "key-case",
+        &t.filename(),
+    ]);
+    let warning: serde_json::Value = serde_json::from_str(&t.expect_exit_code_stdout(1)).unwrap();
+    check_eq("key-case", warning["rule"].as_str().unwrap());
+    check_eq("2", &warning["span"]["line"].to_string());
+});
+
+tomltest!(check_disable, |mut t: TestCaseState| {
+    t.write_file("x = [1, 2.5]  \n");
+    t.cmd.args([
+        "check",
+        "--disable",
+        "mixed-array",
+        "--disable",
+        "trailing-whitespace",
+        &t.filename(),
+    ]);
+    check_eq("", &t.expect_success());
+});
+
+tomltest!(check_lookalike_and_indentation, |mut t: TestCaseState| {
+    t.write_file("[a]\n  \"n\u{430}me\" = 1\n\tb = { long = \"inline table\" }\n");
+    t.cmd
+        .args(["check", "--max-inline-width", "20", &t.filename()]);
+    let stdout = t.expect_exit_code_stdout(1);
+    let file = t.filename();
+    check_eq(
+        &format!(
+            "\
+{file}:2:3: lookalike-key: key `a.\"n\u{430}me\"` looks like `a.name`, but has U+0430
+{file}:3:1: indentation: indented with tabs, but line 2 is indented with spaces
+{file}:3:6: long-inline-table: inline table `a.b` is 25 characters long, more than 20
+"
+        ),
+        &stdout,
+    );
+});
+
+tomltest!(check_missing_file, |mut t: TestCaseState| {
+    t.write_file("a = 1\n");
+    let missing = format!("{}/nosuch.toml", t.dir.path().display());
+    t.cmd.args(["check", &missing, &t.filename()]);
+    check_contains(&format!("toml: {missing}: "), &t.expect_exit_code(3));
+});
+
+tomltest!(check_indentation_per_block, |mut t: TestCaseState| {
+    // Each block can pick its own indentation, as long as it sticks to it.
+    t.write_file("a = [\n  1,\n\n  2,\n]\nb = [\n\t1,\n  2,\n]\n");
+    t.cmd.args(["check", &t.filename()]);
+    let file = t.filename();
+    check_eq(
+        &format!(
+            "{file}:8:1: indentation: indented with spaces, but line 7 is indented with tabs\n"
+        ),
+        &t.expect_exit_code_stdout(1),
+    );
+});
+
 struct TestCaseState {
     cmd: process::Command,
-    #[allow(dead_code)] // We keep the TempDir around to prolong its lifetime
     dir: TempDir,
     filename: PathBuf,
 }
@@ -181,6 +1706,26 @@ impl TestCaseState {
         String::from_utf8(out.stderr).unwrap()
     }
 
+    pub fn expect_exit_code(&mut self, code: i32) -> String {
+        String::from_utf8(self.expect_exit_code_output(code).stderr).unwrap()
+    }
+
+    /// Like `expect_exit_code`, but return what the command printed to stdout.
+    pub fn expect_exit_code_stdout(&mut self, code: i32) -> String {
+        String::from_utf8(self.expect_exit_code_output(code).stdout).unwrap()
+    }
+
+    fn expect_exit_code_output(&mut self, code: i32) -> Output {
+        let out = self.cmd.output().unwrap();
+        if out.status.code() != Some(code) {
+            self.fail(
+                &out,
+                &format!("Command exited with wrong status; expected {code}"),
+            );
+        }
+        out
+    }
+
     fn fail(&self, out: &Output, summary: &str) {
         panic!(
             "\n============\
@@ -202,6 +1747,12 @@ impl TestCaseState {
         fs::write(&self.filename, contents).expect("failed to write test fixture");
     }
 
+    pub fn write_file_named(&self, name: &str, contents: &str) -> String {
+        let path = self.dir.path().join(name);
+        fs::write(&path, contents).expect("failed to write test fixture");
+        String::from(path.to_str().unwrap())
+    }
+
     pub fn filename(&self) -> String {
         // TODO we don't really need a String here, do we?
         String::from(self.filename.as_os_str().to_str().unwrap())